@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::Discriminator;
+use anchor_spl::token::{self, Burn, Mint, Token, TokenAccount, Transfer};
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::mem::size_of;
@@ -9,6 +10,41 @@ declare_id!("GfXYYi5TFPG5ixdfiXLQacgjZbatqpb9uZZPMTBxMVCx");
 const FULL_100: u64 = 100_000_000_000;
 const ACC_PRECISION: u128 = 100_000_000_000;
 
+const MAX_EMISSION_STEPS: usize = 10;
+const MAX_HALVINGS: i64 = 64;
+const MAX_REWARD_STREAMS: usize = 8;
+const MAX_POOLS: usize = 32;
+
+const POOL_RATE_GLOBAL: u8 = 0; // share of state emission by point
+const POOL_RATE_FIXED: u8 = 1; // own token_per_second
+
+const PENALTY_TO_TREASURY: u8 = 0;
+const PENALTY_REDISTRIBUTE: u8 = 1;
+const PENALTY_BURN: u8 = 2;
+
+const VESTING_NONE: u8 = 0;
+const VESTING_ALL: u8 = 1; // the whole harvested reward vests
+const VESTING_EXTRA: u8 = 2; // only the extra tier reward vests
+
+const MAX_EXTRA_CONFIGS: usize = 10;
+const SECONDS_PER_DAY: i64 = 86_400;
+const MAX_KEEPER_TIP: u64 = 10_000_000_000; // 10% of FULL_100
+const MAX_QUEUED_CHANGES: usize = 8;
+const MAX_TIMELOCK_DELAY: i64 = 30 * SECONDS_PER_DAY;
+
+// accounts of the first version, grown since, with the discriminator
+const LEGACY_STATE_SIZE: usize = 8 + 121;
+const LEGACY_POOL_SIZE: usize = 8 + 153;
+const LEGACY_EXTRA_REWARDS_SIZE: usize = 8 + 197;
+
+// queued admin changes
+const CHANGE_TOKENS_PER_SECOND: u8 = 0;
+const CHANGE_POOL_POINT: u8 = 1;
+const CHANGE_EXTRA_REWARD_CONFIGS: u8 = 2;
+const CHANGE_TIMELOCK_DELAY: u8 = 3;
+const CHANGE_EMISSION_SCHEDULE: u8 = 4;
+const CHANGE_POOL_RATE_MODE: u8 = 5;
+
 #[program]
 pub mod neonomad_staking {
     use super::*;
@@ -16,15 +52,48 @@ pub mod neonomad_staking {
     pub fn create_state(
         _ctx: Context<CreateState>,
         bump: u8,
+        state_id: u64,
         token_per_second: u64,
     ) -> ProgramResult {
+        // a state being migrated is only created again by finish_migrate_state
+        let (migration, _) = Pubkey::find_program_address(
+            &[b"migration".as_ref(), _ctx.accounts.state.key().as_ref()],
+            _ctx.program_id,
+        );
+        require!(
+            _ctx.accounts.migration.key() == migration && _ctx.accounts.migration.lamports() == 0,
+            ErrorCode::MigrationPending
+        );
         let state = &mut _ctx.accounts.state.load_init()?;
         state.authority = _ctx.accounts.authority.key();
         state.bump = bump;
+        state.state_id = state_id;
         state.start_time = _ctx.accounts.clock.unix_timestamp;
         state.token_per_second = token_per_second;
         state.reward_mint = _ctx.accounts.reward_mint.key();
         state.reward_vault = _ctx.accounts.reward_vault.key();
+        emit!(StateCreated {
+            state: _ctx.accounts.state.key(),
+            state_id,
+            authority: _ctx.accounts.authority.key(),
+            reward_mint: _ctx.accounts.reward_mint.key()
+        });
+        Ok(())
+    }
+
+    pub fn create_pool_registry(_ctx: Context<CreatePoolRegistry>, bump: u8) -> ProgramResult {
+        let registry = &mut _ctx.accounts.registry;
+        registry.bump = bump;
+        Ok(())
+    }
+
+    // for pools created before the registry existed
+    pub fn register_pool(_ctx: Context<RegisterPool>) -> ProgramResult {
+        let registry = &mut _ctx.accounts.registry;
+        registry.add_pool(_ctx.accounts.pool.key())?;
+        emit!(PoolRegistered {
+            pool: _ctx.accounts.pool.key()
+        });
         Ok(())
     }
 
@@ -36,8 +105,35 @@ pub mod neonomad_staking {
         let extra_account = &mut _ctx.accounts.extra_reward_account;
         extra_account.authority = _ctx.accounts.authority.key();
         extra_account.bump = bump;
+        extra_account.state = _ctx.accounts.state.key();
+        _ctx.accounts.state.load()?.validate_extra_reward_configs(&configs)?;
+        extra_account.configs = configs;
+        extra_account.version = 1;
+        extra_account.validate()?;
+        Ok(())
+    }
+
+    pub fn create_pool_extra_reward_configs(
+        _ctx: Context<CreatePoolExtraRewardsConfigs>,
+        bump: u8,
+        configs: Vec<DurationExtraRewardConfig>,
+    ) -> ProgramResult {
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        extra_account.authority = _ctx.accounts.authority.key();
+        extra_account.bump = bump;
+        extra_account.pool = _ctx.accounts.pool.key();
+        extra_account.state = pool.state;
+        _ctx.accounts.state.load()?.validate_extra_reward_configs(&configs)?;
         extra_account.configs = configs;
+        extra_account.version = 1;
         extra_account.validate()?;
+
+        pool.extra_reward_account = extra_account.key();
+        emit!(PoolExtraRewardConfigsCreated {
+            pool: _ctx.accounts.pool.key(),
+            extra_reward_account: extra_account.key()
+        });
         Ok(())
     }
 
@@ -45,17 +141,36 @@ pub mod neonomad_staking {
         _ctx: Context<SetExtraRewardsConfigs>,
         configs: Vec<DurationExtraRewardConfig>,
     ) -> ProgramResult {
+        let state = _ctx.accounts.state.load()?;
+        require!(state.timelock_delay == 0, ErrorCode::TimelockRequired);
+        state.validate_extra_reward_configs(&configs)?;
         let extra_account = &mut _ctx.accounts.extra_reward_account;
-        extra_account.configs = configs;
+        extra_account.set_configs(configs)?;
+        emit!(ExtraRewardConfigsChanged {
+            extra_reward_account: extra_account.key(),
+            version: extra_account.version
+        });
+        Ok(())
+    }
+
+    pub fn set_early_unstake_penalty(
+        _ctx: Context<SetEarlyUnstakePenalty>,
+        penalty: EarlyUnstakePenaltyConfig,
+    ) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        extra_account.penalty = penalty;
+        extra_account.version = extra_account.version.checked_add(1).unwrap();
         extra_account.validate()?;
+        emit!(ExtraRewardConfigsChanged {
+            extra_reward_account: extra_account.key(),
+            version: extra_account.version
+        });
         Ok(())
     }
 
     pub fn fund_reward_token(_ctx: Context<Fund>, amount: u64) -> ProgramResult {
         msg!("funding...");
         let mut state = _ctx.accounts.state.load_mut()?;
-        let mut pool = _ctx.accounts.pool.load_mut()?;
-        msg!("loaded state, pool");
         let cpi_accounts = Transfer {
             from: _ctx.accounts.user_vault.to_account_info(),
             to: _ctx.accounts.reward_vault.to_account_info(),
@@ -66,16 +181,43 @@ pub mod neonomad_staking {
         token::transfer(cpi_ctx, amount)?;
         msg!("funded {}", amount);
 
-        state.total_point = state
-            .total_point
-            .checked_add(amount)
-            .unwrap();
-        pool.point = pool
-            .point
-            .checked_add(amount)
-            .unwrap();
-        pool.update(&state, &_ctx.accounts.clock)?;
-        msg!("updated pool");
+        state.total_funded = state.total_funded.checked_add(amount).unwrap();
+        emit!(RewardFunded {
+            authority: _ctx.accounts.authority.key(),
+            amount,
+            total_funded: state.total_funded
+        });
+        Ok(())
+    }
+
+    pub fn get_reward_runway(_ctx: Context<GetRewardRunway>) -> ProgramResult {
+        let state = _ctx.accounts.state.load()?;
+        let now = _ctx.accounts.clock.unix_timestamp;
+        emit!(RewardRunway {
+            available: state.get_available_reward(),
+            committed: state.reward_committed,
+            token_per_second: state.get_token_per_second(now),
+            runway: state.get_runway(now)
+        });
+        Ok(())
+    }
+
+    pub fn set_emission_schedule(
+        _ctx: Context<ChangeTokensPerSecond>,
+        end_time: i64,
+        steps: Vec<EmissionStep>,
+        halving_interval: i64,
+    ) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        require!(state.timelock_delay == 0, ErrorCode::TimelockRequired);
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+        _ctx.accounts.registry.validate_pools(_ctx.remaining_accounts)?;
+        state.set_emission_schedule(end_time, &steps, halving_interval, _ctx.accounts.clock.unix_timestamp)?;
+        emit!(EmissionScheduleChanged {
+            end_time,
+            steps: steps.len() as u64,
+            halving_interval
+        });
         Ok(())
     }
 
@@ -84,16 +226,10 @@ pub mod neonomad_staking {
         token_per_second: u64,
     ) -> ProgramResult {
         let mut state = _ctx.accounts.state.load_mut()?;
-        let provided_remaining_accounts = &mut _ctx.remaining_accounts.iter();
-        let mut i = 0;
-        
-        while i < _ctx.remaining_accounts.len(){
-            i +=1;
-            let provided_token_accountinfo = next_account_info(provided_remaining_accounts)?;
-            let loader = Loader::<FarmPoolAccount>::try_from(&_ctx.program_id, &provided_token_accountinfo)?;
-            loader.load_mut()?.update(&state, &_ctx.accounts.clock)?;
-        }
-        state.token_per_second = token_per_second;
+        require!(state.timelock_delay == 0, ErrorCode::TimelockRequired);
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+        _ctx.accounts.registry.validate_pools(_ctx.remaining_accounts)?;
+        state.set_token_per_second(token_per_second, _ctx.accounts.clock.unix_timestamp)?;
         emit!(RateChanged { token_per_second });
         Ok(())
     }
@@ -101,335 +237,2263 @@ pub mod neonomad_staking {
     pub fn create_pool(
         _ctx: Context<CreateFarmPool>,
         bump: u8,
+        pool_id: u64,
         point: u64,
-        amount_multipler: u64,
     ) -> ProgramResult {
-         let mut state = _ctx.accounts.state.load_mut()?;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
 
         let pool = &mut _ctx.accounts.pool.load_init()?;
         pool.bump = bump;
+        pool.pool_id = pool_id;
+        pool.state = _ctx.accounts.state.key();
+        pool.state_id = state.state_id;
         pool.mint = _ctx.accounts.mint.key();
         pool.vault = _ctx.accounts.vault.key();
         pool.point = point;
-        pool.amount_multipler = amount_multipler;
         pool.authority = _ctx.accounts.authority.key();
 
-        state.total_point = state.total_point.checked_add(point).unwrap();
+        // under a timelock the pool starts without a share, its point is queued
+        require!(
+            state.timelock_delay == 0 || pool.share_point() == 0,
+            ErrorCode::TimelockRequired
+        );
+        if pool.share_point() > 0 {
+            _ctx.accounts.registry.validate_pools(_ctx.remaining_accounts)?;
+        }
+        _ctx.accounts.registry.add_pool(_ctx.accounts.pool.key())?;
+        state.total_point = state.total_point.checked_add(pool.share_point()).unwrap();
 
         emit!(PoolCreated {
             pool: _ctx.accounts.pool.key(),
-            mint: _ctx.accounts.mint.key()
+            mint: _ctx.accounts.mint.key(),
+            pool_id
         });
         Ok(())
     }
 
     pub fn close_pool(_ctx: Context<CloseFarmPool>) -> ProgramResult {
         let mut state = _ctx.accounts.state.load_mut()?;
-
-        let provided_remaining_accounts = &mut _ctx.remaining_accounts.iter();
-        let mut i = 0;
-        
-        while i < _ctx.remaining_accounts.len(){
-            i +=1;
-
-            let provided_token_accountinfo = next_account_info(provided_remaining_accounts)?;
-            let loader = Loader::<FarmPoolAccount>::try_from(&_ctx.program_id, &provided_token_accountinfo)?;
-            loader.load_mut()?.update(&state, &_ctx.accounts.clock)?;
-        }
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
         let pool = _ctx.accounts.pool.load()?;
         require!(pool.amount == 0, ErrorCode::WorkingPool);
-        state.total_point = state.total_point.checked_sub(pool.point).unwrap();
+        // under a timelock the share is taken away through a queued point change first
+        require!(
+            state.timelock_delay == 0 || pool.share_point() == 0,
+            ErrorCode::TimelockRequired
+        );
+        let registry = &mut _ctx.accounts.registry;
+        registry.validate_pools(_ctx.remaining_accounts)?;
+        let pool_key = _ctx.accounts.pool.key();
+        registry.pools.retain(|p| *p != pool_key);
+        state.total_point = state.total_point.checked_sub(pool.share_point()).unwrap();
+        state.total_pool_token_per_second = state
+            .total_pool_token_per_second
+            .checked_sub(pool.get_fixed_token_per_second())
+            .unwrap();
         Ok(())
     }
 
-    pub fn change_pool_amount_multipler(
-        _ctx: Context<ChangePoolSetting>,
-        amount_multipler: u64,
+    pub fn change_pool_unbonding_period(
+        _ctx: Context<ChangePoolTerms>,
+        unbonding_period: i64,
     ) -> ProgramResult {
+        require!(unbonding_period >= 0, ErrorCode::InvalidUnbondingPeriod);
         let mut pool = _ctx.accounts.pool.load_mut()?;
-        pool.amount_multipler = amount_multipler;
-        emit!(PoolAmountMultiplerChanged {
+        pool.unbonding_period = unbonding_period;
+        emit!(PoolUnbondingPeriodChanged {
             pool: _ctx.accounts.pool.key(),
-            amount_multipler
+            unbonding_period
         });
         Ok(())
     }
 
-    pub fn change_pool_point(_ctx: Context<ChangePoolSetting>, point: u64) -> ProgramResult {
-        let mut state = _ctx.accounts.state.load_mut()?;
-        let provided_remaining_accounts = &mut _ctx.remaining_accounts.iter();
-        let mut i = 0;
-        
-        while i < _ctx.remaining_accounts.len(){
-            i +=1;
-
-            let provided_token_accountinfo = next_account_info(provided_remaining_accounts)?;
-            let loader = Loader::<FarmPoolAccount>::try_from(&_ctx.program_id, &provided_token_accountinfo)?;
-            loader.load_mut()?.update(&state, &_ctx.accounts.clock)?;
-        }
+    pub fn change_pool_vesting(
+        _ctx: Context<ChangePoolTerms>,
+        vesting_mode: u8,
+        vesting_period: i64,
+        vesting_early_claim_penalty: u64,
+    ) -> ProgramResult {
+        require!(vesting_mode <= VESTING_EXTRA, ErrorCode::InvalidVesting);
+        require!(vesting_mode == VESTING_NONE || vesting_period > 0, ErrorCode::InvalidVesting);
+        require!(vesting_early_claim_penalty <= FULL_100, ErrorCode::InvalidVesting);
         let mut pool = _ctx.accounts.pool.load_mut()?;
-        state.total_point = state
-            .total_point
-            .checked_sub(pool.point)
-            .unwrap()
-            .checked_add(point)
-            .unwrap();
-        pool.point = point;
-        emit!(PoolPointChanged {
+        pool.vesting_mode = vesting_mode;
+        pool.vesting_period = vesting_period;
+        pool.vesting_early_claim_penalty = vesting_early_claim_penalty;
+        emit!(PoolVestingChanged {
             pool: _ctx.accounts.pool.key(),
-            point
+            vesting_mode,
+            vesting_period,
+            vesting_early_claim_penalty
         });
         Ok(())
     }
 
-    pub fn create_user(_ctx: Context<CreatePoolUser>, bump: u8) -> ProgramResult {
-        let user = &mut _ctx.accounts.user.load_init()?;
-        user.authority = _ctx.accounts.authority.key();
-        user.bump = bump;
-        user.pool = _ctx.accounts.pool.key();
-
+    pub fn change_pool_keeper_tip(
+        _ctx: Context<ChangePoolTerms>,
+        keeper_tip_percentage: u64,
+    ) -> ProgramResult {
+        require!(keeper_tip_percentage <= MAX_KEEPER_TIP, ErrorCode::InvalidKeeperTip);
         let mut pool = _ctx.accounts.pool.load_mut()?;
-        pool.total_user += 1;
-        emit!(UserCreated {
+        pool.keeper_tip_percentage = keeper_tip_percentage;
+        emit!(PoolKeeperTipChanged {
             pool: _ctx.accounts.pool.key(),
-            user: _ctx.accounts.user.key(),
-            authority: _ctx.accounts.authority.key(),
+            keeper_tip_percentage
         });
         Ok(())
     }
 
-    pub fn create_user_ether_address(
-        _ctx: Context<CreateUserEtherAddress>,
-        bump: u8,
-        ether_address: String,
+    pub fn change_pool_emergency_unlock(
+        _ctx: Context<ChangePoolTerms>,
+        emergency_unlocked: bool,
     ) -> ProgramResult {
-        let user = &mut _ctx.accounts.user.load_init()?;
-        let src = ether_address.as_bytes();
-        let mut data = [0u8; 42];
-        data[..src.len()].copy_from_slice(src);
-        user.ether_address = data;
-        user.bump = bump;
-        user.authority = _ctx.accounts.authority.key();
-        emit!(UserEtherAddressChanged {
-            authority: _ctx.accounts.authority.key(),
-            ether_address
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.emergency_unlocked = emergency_unlocked as u8;
+        emit!(PoolEmergencyUnlockChanged {
+            pool: _ctx.accounts.pool.key(),
+            emergency_unlocked
         });
         Ok(())
     }
 
-    pub fn set_user_ether_address(
-        _ctx: Context<SetUserEtherAddress>,
-        ether_address: String,
+    pub fn change_pool_rate_mode(
+        _ctx: Context<ChangePoolSetting>,
+        rate_mode: u8,
+        token_per_second: u64,
     ) -> ProgramResult {
-        let mut user = _ctx.accounts.user.load_mut()?;
-        let src = ether_address.as_bytes();
-        let mut data = [0u8; 42];
-        data[..src.len()].copy_from_slice(src);
-        user.ether_address = data;
-        emit!(UserEtherAddressChanged {
-            authority: _ctx.accounts.authority.key(),
-            ether_address
+        require!(rate_mode <= POOL_RATE_FIXED, ErrorCode::InvalidRateMode);
+        let mut state = _ctx.accounts.state.load_mut()?;
+        require!(state.timelock_delay == 0, ErrorCode::TimelockRequired);
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        set_pool_rate_mode(&mut state, &mut pool, &_ctx.accounts.registry, _ctx.remaining_accounts, rate_mode, token_per_second)?;
+        emit!(PoolRateModeChanged {
+            pool: _ctx.accounts.pool.key(),
+            rate_mode,
+            token_per_second
         });
         Ok(())
     }
 
-    pub fn stake(_ctx: Context<Stake>, amount: u64, lock_duration: i64) -> ProgramResult {
-        msg!("staking...");
-        let state = _ctx.accounts.state.load()?;
-        let extra_account = &mut _ctx.accounts.extra_reward_account;
-        let mut user = _ctx.accounts.user.load_mut()?;
+    pub fn change_pool_point(_ctx: Context<ChangePoolSetting>, point: u64) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        require!(state.timelock_delay == 0, ErrorCode::TimelockRequired);
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        change_pool_weight(&mut state, &mut pool, &_ctx.accounts.registry, _ctx.remaining_accounts, point)?;
+        emit!(PoolPointChanged {
+            pool: _ctx.accounts.pool.key(),
+            point
+        });
+        Ok(())
+    }
+
+    pub fn create_reward_stream(
+        _ctx: Context<CreateRewardStream>,
+        bump: u8,
+        token_per_second: u64,
+        end_time: i64,
+    ) -> ProgramResult {
+        require!(end_time >= 0, ErrorCode::InvalidSchedule);
         let mut pool = _ctx.accounts.pool.load_mut()?;
-        msg!("loaded states");
-        extra_account.validate_lock_duration(&lock_duration)?;
-        msg!("passed validate_lock_duration");
         require!(
-            lock_duration >= user.lock_duration,
-            ErrorCode::InvalidLockDuration
+            usize::from(pool.stream_count) < MAX_REWARD_STREAMS,
+            ErrorCode::TooManyStreams
         );
-        msg!("passed lock_duration >= user.lock_duration");
-
-        pool.update(&state, &_ctx.accounts.clock)?;
-        msg!("updated state");
-        let user_lock_duration = user.lock_duration;
-        user.calculate_reward_amount(&pool, &extra_account.get_extra_reward_percentage(&user_lock_duration))?;
-        msg!("calculate_reward_amount");
-        user.amount = user.amount.checked_add(amount).unwrap();
-        pool.amount = pool.amount.checked_add(amount).unwrap();
+        let stream = &mut _ctx.accounts.stream.load_init()?;
+        stream.bump = bump;
+        stream.pool = _ctx.accounts.pool.key();
+        stream.index = pool.stream_count;
+        stream.authority = _ctx.accounts.authority.key();
+        stream.mint = _ctx.accounts.mint.key();
+        stream.vault = _ctx.accounts.vault.key();
+        stream.token_per_second = token_per_second;
+        stream.end_time = end_time;
+        stream.last_reward_time = _ctx.accounts.clock.unix_timestamp;
+        pool.stream_count = pool.stream_count.checked_add(1).unwrap();
+        emit!(RewardStreamCreated {
+            pool: _ctx.accounts.pool.key(),
+            stream: _ctx.accounts.stream.key(),
+            mint: _ctx.accounts.mint.key(),
+            index: stream.index,
+            token_per_second,
+            end_time
+        });
+        Ok(())
+    }
 
-        user.lock_duration = lock_duration;
-        user.calculate_reward_debt(&pool)?;
-        user.last_stake_time = _ctx.accounts.clock.unix_timestamp;
-        msg!("calculate_reward_debt");
+    pub fn fund_reward_stream(_ctx: Context<FundRewardStream>, amount: u64) -> ProgramResult {
+        let mut stream = _ctx.accounts.stream.load_mut()?;
         let cpi_accounts = Transfer {
             from: _ctx.accounts.user_vault.to_account_info(),
-            to: _ctx.accounts.pool_vault.to_account_info(),
+            to: _ctx.accounts.stream_vault.to_account_info(),
             authority: _ctx.accounts.authority.to_account_info(),
         };
         let cpi_program = _ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
         token::transfer(cpi_ctx, amount)?;
-        msg!("staked {}", amount);
-        emit!(UserStaked {
-            pool: _ctx.accounts.pool.key(),
-            user: _ctx.accounts.user.key(),
+
+        stream.total_funded = stream.total_funded.checked_add(amount).unwrap();
+        emit!(RewardStreamFunded {
+            stream: _ctx.accounts.stream.key(),
             authority: _ctx.accounts.authority.key(),
             amount,
-            lock_duration
+            total_funded: stream.total_funded
         });
         Ok(())
     }
 
-    pub fn unstake(_ctx: Context<Stake>, amount: u64) -> ProgramResult {
-        let extra_account = &mut _ctx.accounts.extra_reward_account;
-        let state = _ctx.accounts.state.load()?;
-        let mut user = _ctx.accounts.user.load_mut()?;
-        let mut pool = _ctx.accounts.pool.load_mut()?;
-
-        require!(user.amount >= amount, ErrorCode::UnstakeOverAmount);
-        require!(
-            user.last_stake_time
-                .checked_add(user.lock_duration)
-                .unwrap()
-                <= _ctx.accounts.clock.unix_timestamp,
-            ErrorCode::UnderLocked
-        );
+    pub fn change_reward_stream(
+        _ctx: Context<ChangeRewardStream>,
+        token_per_second: u64,
+        end_time: i64,
+    ) -> ProgramResult {
+        require!(end_time >= 0, ErrorCode::InvalidSchedule);
+        let pool = _ctx.accounts.pool.load()?;
+        let mut stream = _ctx.accounts.stream.load_mut()?;
+        stream.update(pool.amount, _ctx.accounts.clock.unix_timestamp);
+        stream.token_per_second = token_per_second;
+        stream.end_time = end_time;
+        emit!(RewardStreamChanged {
+            stream: _ctx.accounts.stream.key(),
+            token_per_second,
+            end_time
+        });
+        Ok(())
+    }
 
-        pool.update(&state, &_ctx.accounts.clock)?;
-        let user_lock_duration = user.lock_duration;
-        user.calculate_reward_amount(&pool, &extra_account.get_extra_reward_percentage(&user_lock_duration))?;
+    pub fn set_roles(
+        _ctx: Context<ChangeStateAuthority>,
+        funder: Pubkey,
+        operator: Pubkey,
+        pauser: Pubkey,
+    ) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        state.funder = funder;
+        state.operator = operator;
+        state.pauser = pauser;
+        emit!(RolesChanged {
+            state: _ctx.accounts.state.key(),
+            funder,
+            operator,
+            pauser
+        });
+        Ok(())
+    }
 
-        user.last_stake_time = _ctx.accounts.clock.unix_timestamp;
-        user.amount = user.amount.checked_sub(amount).unwrap();
-        pool.amount = pool.amount.checked_sub(amount).unwrap();
+    pub fn set_caps(
+        _ctx: Context<ChangeStateAuthority>,
+        max_token_per_second: u64,
+        max_extra_percentage: u64,
+        max_rate_change_per_day: u64,
+        max_lock_duration: i64,
+    ) -> ProgramResult {
+        require!(max_extra_percentage <= FULL_100, ErrorCode::ExtraPercentageTooHigh);
+        require!(max_lock_duration >= 0, ErrorCode::InvalidLockDuration);
+        let mut state = _ctx.accounts.state.load_mut()?;
+        state.max_token_per_second = max_token_per_second;
+        state.max_extra_percentage = max_extra_percentage;
+        state.max_rate_change_per_day = max_rate_change_per_day;
+        state.max_lock_duration = max_lock_duration;
+        emit!(CapsChanged {
+            state: _ctx.accounts.state.key(),
+            max_token_per_second,
+            max_extra_percentage,
+            max_rate_change_per_day,
+            max_lock_duration
+        });
+        Ok(())
+    }
 
-        if user.amount == 0
-        {
-            user.lock_duration = 0;
+    pub fn set_paused(
+        _ctx: Context<SetPaused>,
+        paused: bool,
+        unstake_paused: bool,
+    ) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        // the pauser can only pause, resuming is up to the authority
+        if state.authority != _ctx.accounts.authority.key() {
+            require!(
+                paused as u8 >= state.paused && unstake_paused as u8 >= state.unstake_paused,
+                ErrorCode::NotAuthorizedToResume
+            );
         }
-
-        user.calculate_reward_debt(&pool)?;
-        drop(pool);
-
-        let new_pool = _ctx.accounts.pool.load()?;
-        let cpi_accounts = Transfer {
-            from: _ctx.accounts.pool_vault.to_account_info(),
-            to: _ctx.accounts.user_vault.to_account_info(),
-            authority: _ctx.accounts.pool.to_account_info(),
-        };
-
-        let seeds = &[new_pool.mint.as_ref(), &[new_pool.bump]];
-        let signer = &[&seeds[..]];
-        let cpi_program = _ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, amount)?;
-        emit!(UserUnstaked {
-            pool: _ctx.accounts.pool.key(),
-            user: _ctx.accounts.user.key(),
+        state.paused = paused as u8;
+        state.unstake_paused = unstake_paused as u8;
+        emit!(PauseChanged {
+            account: _ctx.accounts.state.key(),
             authority: _ctx.accounts.authority.key(),
-            amount
+            paused,
+            unstake_paused
         });
         Ok(())
     }
 
-    pub fn harvest(_ctx: Context<Harvest>) -> ProgramResult {
-        let extra_account = &mut _ctx.accounts.extra_reward_account;
+    pub fn set_pool_paused(
+        _ctx: Context<SetPoolPaused>,
+        paused: bool,
+        unstake_paused: bool,
+    ) -> ProgramResult {
         let state = _ctx.accounts.state.load()?;
         let mut pool = _ctx.accounts.pool.load_mut()?;
-        let mut user = _ctx.accounts.user.load_mut()?;
+        let authority = _ctx.accounts.authority.key();
+        if state.authority != authority && pool.authority != authority {
+            require!(
+                paused as u8 >= pool.paused && unstake_paused as u8 >= pool.unstake_paused,
+                ErrorCode::NotAuthorizedToResume
+            );
+        }
+        pool.paused = paused as u8;
+        pool.unstake_paused = unstake_paused as u8;
+        emit!(PauseChanged {
+            account: _ctx.accounts.pool.key(),
+            authority,
+            paused,
+            unstake_paused
+        });
+        Ok(())
+    }
 
-        pool.update(&state, &_ctx.accounts.clock)?;
-        let user_lock_duration = user.lock_duration;
-        user.calculate_reward_amount(&pool, &extra_account.get_extra_reward_percentage(&user_lock_duration))?;
+    pub fn create_admin_queue(_ctx: Context<CreateAdminQueue>, bump: u8) -> ProgramResult {
+        let queue = &mut _ctx.accounts.queue;
+        queue.bump = bump;
+        queue.state = _ctx.accounts.state.key();
+        Ok(())
+    }
 
-        let total_reward = user.reward_amount.checked_add(user.extra_reward).unwrap().try_into().unwrap();
+    // raising the delay applies directly, lowering it goes through the queue
+    pub fn set_timelock_delay(_ctx: Context<ChangeStateAuthority>, timelock_delay: i64) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        require!(timelock_delay >= state.timelock_delay, ErrorCode::TimelockRequired);
+        require!(timelock_delay <= MAX_TIMELOCK_DELAY, ErrorCode::TimelockTooLong);
+        state.timelock_delay = timelock_delay;
+        emit!(TimelockDelayChanged {
+            state: _ctx.accounts.state.key(),
+            timelock_delay
+        });
+        Ok(())
+    }
 
-        let cpi_accounts = Transfer {
-            from: _ctx.accounts.reward_vault.to_account_info(),
-            to: _ctx.accounts.user_vault.to_account_info(),
-            authority: _ctx.accounts.state.to_account_info(),
-        };
+    pub fn queue_change(
+        _ctx: Context<QueueChange>,
+        kind: u8,
+        target: Pubkey,
+        value: u64,
+        configs: Vec<DurationExtraRewardConfig>,
+        eta: i64,
+    ) -> ProgramResult {
+        // the schedule and the rate mode have their own queued forms
+        require!(kind < CHANGE_EMISSION_SCHEDULE, ErrorCode::InvalidQueuedChange);
+        if kind == CHANGE_TIMELOCK_DELAY {
+            require!(value <= MAX_TIMELOCK_DELAY as u64, ErrorCode::TimelockTooLong);
+        }
+        require!(configs.len() <= MAX_EXTRA_CONFIGS, ErrorCode::InvalidQueuedChange);
+        queue_admin_change(
+            _ctx.accounts,
+            QueuedChange {
+                kind,
+                target,
+                value,
+                configs,
+                eta,
+                ..QueuedChange::default()
+            },
+        )?;
+        Ok(())
+    }
 
-        let seeds = &[b"state".as_ref(), &[state.bump]];
-        let signer = &[&seeds[..]];
-        let cpi_program = _ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, total_reward)?;
+    pub fn queue_emission_schedule(
+        _ctx: Context<QueueChange>,
+        end_time: i64,
+        steps: Vec<EmissionStep>,
+        halving_interval: i64,
+        eta: i64,
+    ) -> ProgramResult {
+        require!(steps.len() <= MAX_EMISSION_STEPS, ErrorCode::InvalidSchedule);
+        queue_admin_change(
+            _ctx.accounts,
+            QueuedChange {
+                kind: CHANGE_EMISSION_SCHEDULE,
+                end_time,
+                steps,
+                halving_interval,
+                eta,
+                ..QueuedChange::default()
+            },
+        )?;
+        Ok(())
+    }
 
-        user.reward_amount = 0;
-        user.extra_reward = 0;
-        user.calculate_reward_debt(&pool)?;
-        emit!(UserHarvested {
-            pool: _ctx.accounts.pool.key(),
-            user: _ctx.accounts.user.key(),
-            authority: _ctx.accounts.authority.key(),
-            amount: total_reward
-        });
+    pub fn queue_pool_rate_mode(
+        _ctx: Context<QueueChange>,
+        pool: Pubkey,
+        rate_mode: u8,
+        token_per_second: u64,
+        eta: i64,
+    ) -> ProgramResult {
+        require!(rate_mode <= POOL_RATE_FIXED, ErrorCode::InvalidRateMode);
+        queue_admin_change(
+            _ctx.accounts,
+            QueuedChange {
+                kind: CHANGE_POOL_RATE_MODE,
+                target: pool,
+                value: token_per_second,
+                rate_mode,
+                eta,
+                ..QueuedChange::default()
+            },
+        )?;
         Ok(())
     }
-}
 
-#[derive(Accounts)]
+    pub fn cancel_change(_ctx: Context<QueueChange>, id: u64) -> ProgramResult {
+        let state = _ctx.accounts.state.load()?;
+        let queue = &mut _ctx.accounts.queue;
+        let change = queue.remove(id)?;
+        state.assert_can_queue(change.kind, &_ctx.accounts.authority.key())?;
+        emit!(ChangeCancelled {
+            state: _ctx.accounts.state.key(),
+            id
+        });
+        Ok(())
+    }
+
+    // anyone can execute a due change, pools are settled as in the direct instructions
+    pub fn execute_state_change(_ctx: Context<ExecuteStateChange>, id: u64) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let change = _ctx.accounts.queue.take_due(id, _ctx.accounts.clock.unix_timestamp)?;
+        if change.kind == CHANGE_TOKENS_PER_SECOND {
+            update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+            _ctx.accounts.registry.validate_pools(_ctx.remaining_accounts)?;
+            state.set_token_per_second(change.value, _ctx.accounts.clock.unix_timestamp)?;
+            emit!(RateChanged { token_per_second: change.value });
+        } else if change.kind == CHANGE_EMISSION_SCHEDULE {
+            update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+            _ctx.accounts.registry.validate_pools(_ctx.remaining_accounts)?;
+            state.set_emission_schedule(change.end_time, &change.steps, change.halving_interval, _ctx.accounts.clock.unix_timestamp)?;
+            emit!(EmissionScheduleChanged {
+                end_time: change.end_time,
+                steps: change.steps.len() as u64,
+                halving_interval: change.halving_interval
+            });
+        } else if change.kind == CHANGE_TIMELOCK_DELAY {
+            state.timelock_delay = i64::try_from(change.value).unwrap();
+            emit!(TimelockDelayChanged {
+                state: _ctx.accounts.state.key(),
+                timelock_delay: state.timelock_delay
+            });
+        } else {
+            return Err(ErrorCode::InvalidQueuedChange.into());
+        }
+        emit!(ChangeExecuted {
+            state: _ctx.accounts.state.key(),
+            id,
+            kind: change.kind,
+            target: change.target
+        });
+        Ok(())
+    }
+
+    pub fn execute_pool_change(_ctx: Context<ExecutePoolChange>, id: u64) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let change = _ctx.accounts.queue.take_due(id, _ctx.accounts.clock.unix_timestamp)?;
+        require!(change.target == _ctx.accounts.pool.key(), ErrorCode::InvalidQueuedChange);
+        update_pools(&mut state, _ctx.program_id, _ctx.remaining_accounts, &_ctx.accounts.clock)?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        if change.kind == CHANGE_POOL_POINT {
+            change_pool_weight(&mut state, &mut pool, &_ctx.accounts.registry, _ctx.remaining_accounts, change.value)?;
+            emit!(PoolPointChanged {
+                pool: change.target,
+                point: change.value
+            });
+        } else if change.kind == CHANGE_POOL_RATE_MODE {
+            set_pool_rate_mode(&mut state, &mut pool, &_ctx.accounts.registry, _ctx.remaining_accounts, change.rate_mode, change.value)?;
+            emit!(PoolRateModeChanged {
+                pool: change.target,
+                rate_mode: change.rate_mode,
+                token_per_second: change.value
+            });
+        } else {
+            return Err(ErrorCode::InvalidQueuedChange.into());
+        }
+        emit!(ChangeExecuted {
+            state: _ctx.accounts.state.key(),
+            id,
+            kind: change.kind,
+            target: change.target
+        });
+        Ok(())
+    }
+
+    pub fn execute_extra_reward_configs_change(
+        _ctx: Context<ExecuteExtraRewardsChange>,
+        id: u64,
+    ) -> ProgramResult {
+        let change = _ctx.accounts.queue.take_due(id, _ctx.accounts.clock.unix_timestamp)?;
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        require!(
+            change.kind == CHANGE_EXTRA_REWARD_CONFIGS && change.target == extra_account.key(),
+            ErrorCode::InvalidQueuedChange
+        );
+        _ctx.accounts.state.load()?.validate_extra_reward_configs(&change.configs)?;
+        extra_account.set_configs(change.configs)?;
+        emit!(ExtraRewardConfigsChanged {
+            extra_reward_account: extra_account.key(),
+            version: extra_account.version
+        });
+        emit!(ChangeExecuted {
+            state: _ctx.accounts.state.key(),
+            id,
+            kind: change.kind,
+            target: change.target
+        });
+        Ok(())
+    }
+
+    pub fn propose_state_authority(
+        _ctx: Context<ChangeStateAuthority>,
+        pending_authority: Pubkey,
+    ) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        state.pending_authority = pending_authority;
+        emit!(AuthorityProposed {
+            account: _ctx.accounts.state.key(),
+            authority: _ctx.accounts.authority.key(),
+            pending_authority
+        });
+        Ok(())
+    }
+
+    pub fn cancel_state_authority(_ctx: Context<ChangeStateAuthority>) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        state.pending_authority = Pubkey::default();
+        emit!(AuthorityProposalCancelled {
+            account: _ctx.accounts.state.key(),
+            authority: _ctx.accounts.authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn accept_state_authority(_ctx: Context<AcceptStateAuthority>) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let previous_authority = state.authority;
+        state.authority = state.pending_authority;
+        state.pending_authority = Pubkey::default();
+        emit!(AuthorityTransferred {
+            account: _ctx.accounts.state.key(),
+            previous_authority,
+            authority: _ctx.accounts.pending_authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn propose_pool_authority(
+        _ctx: Context<ChangePoolAuthority>,
+        pending_authority: Pubkey,
+    ) -> ProgramResult {
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.pending_authority = pending_authority;
+        emit!(AuthorityProposed {
+            account: _ctx.accounts.pool.key(),
+            authority: _ctx.accounts.authority.key(),
+            pending_authority
+        });
+        Ok(())
+    }
+
+    pub fn cancel_pool_authority(_ctx: Context<ChangePoolAuthority>) -> ProgramResult {
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.pending_authority = Pubkey::default();
+        emit!(AuthorityProposalCancelled {
+            account: _ctx.accounts.pool.key(),
+            authority: _ctx.accounts.authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn accept_pool_authority(_ctx: Context<AcceptPoolAuthority>) -> ProgramResult {
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let previous_authority = pool.authority;
+        pool.authority = pool.pending_authority;
+        pool.pending_authority = Pubkey::default();
+        emit!(AuthorityTransferred {
+            account: _ctx.accounts.pool.key(),
+            previous_authority,
+            authority: _ctx.accounts.pending_authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn propose_extra_reward_authority(
+        _ctx: Context<ChangeExtraRewardsAuthority>,
+        pending_authority: Pubkey,
+    ) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        extra_account.pending_authority = pending_authority;
+        emit!(AuthorityProposed {
+            account: extra_account.key(),
+            authority: _ctx.accounts.authority.key(),
+            pending_authority
+        });
+        Ok(())
+    }
+
+    pub fn cancel_extra_reward_authority(_ctx: Context<ChangeExtraRewardsAuthority>) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        extra_account.pending_authority = Pubkey::default();
+        emit!(AuthorityProposalCancelled {
+            account: extra_account.key(),
+            authority: _ctx.accounts.authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn accept_extra_reward_authority(_ctx: Context<AcceptExtraRewardsAuthority>) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let previous_authority = extra_account.authority;
+        extra_account.authority = extra_account.pending_authority;
+        extra_account.pending_authority = Pubkey::default();
+        emit!(AuthorityTransferred {
+            account: extra_account.key(),
+            previous_authority,
+            authority: _ctx.accounts.pending_authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn create_user(_ctx: Context<CreatePoolUser>, bump: u8) -> ProgramResult {
+        let user = &mut _ctx.accounts.user.load_init()?;
+        user.authority = _ctx.accounts.authority.key();
+        user.bump = bump;
+        user.pool = _ctx.accounts.pool.key();
+
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.total_user += 1;
+        emit!(UserCreated {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            authority: _ctx.accounts.authority.key(),
+        });
+        Ok(())
+    }
+
+    pub fn create_user_for(_ctx: Context<CreatePoolUserFor>, bump: u8) -> ProgramResult {
+        let user = &mut _ctx.accounts.user.load_init()?;
+        user.authority = _ctx.accounts.beneficiary.key();
+        user.bump = bump;
+        user.pool = _ctx.accounts.pool.key();
+
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        pool.total_user += 1;
+        emit!(UserCreated {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            authority: _ctx.accounts.beneficiary.key(),
+        });
+        Ok(())
+    }
+
+    pub fn create_user_ether_address(
+        _ctx: Context<CreateUserEtherAddress>,
+        bump: u8,
+        ether_address: String,
+    ) -> ProgramResult {
+        let user = &mut _ctx.accounts.user.load_init()?;
+        let src = ether_address.as_bytes();
+        let mut data = [0u8; 42];
+        data[..src.len()].copy_from_slice(src);
+        user.ether_address = data;
+        user.bump = bump;
+        user.authority = _ctx.accounts.authority.key();
+        emit!(UserEtherAddressChanged {
+            authority: _ctx.accounts.authority.key(),
+            ether_address
+        });
+        Ok(())
+    }
+
+    pub fn set_user_ether_address(
+        _ctx: Context<SetUserEtherAddress>,
+        ether_address: String,
+    ) -> ProgramResult {
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let src = ether_address.as_bytes();
+        let mut data = [0u8; 42];
+        data[..src.len()].copy_from_slice(src);
+        user.ether_address = data;
+        emit!(UserEtherAddressChanged {
+            authority: _ctx.accounts.authority.key(),
+            ether_address
+        });
+        Ok(())
+    }
+
+    pub fn create_position(_ctx: Context<CreatePosition>, bump: u8) -> ProgramResult {
+        let mut user = _ctx.accounts.user.load_mut()?;
+        require!(!user.is_legacy(), ErrorCode::MigrationRequired);
+        let position = &mut _ctx.accounts.position.load_init()?;
+        position.bump = bump;
+        position.pool = _ctx.accounts.pool.key();
+        position.user = _ctx.accounts.user.key();
+        position.authority = _ctx.accounts.authority.key();
+        position.index = user.position_count;
+
+        user.position_count = user.position_count.checked_add(1).unwrap();
+        emit!(PositionCreated {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            index: position.index,
+        });
+        Ok(())
+    }
+
+    // moves the stake of a user account created before positions into position 0,
+    // the lock keeps its legacy terms: no early unstake
+    pub fn migrate_user(_ctx: Context<MigrateUser>, bump: u8) -> ProgramResult {
+        let extra_account = &_ctx.accounts.extra_reward_account;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let position = &mut _ctx.accounts.position.load_init()?;
+
+        require!(user.is_legacy(), ErrorCode::NothingToMigrate);
+        position.bump = bump;
+        position.pool = _ctx.accounts.pool.key();
+        position.user = _ctx.accounts.user.key();
+        position.authority = _ctx.accounts.authority.key();
+        position.index = 0;
+        position.amount = user.amount;
+        position.reward_amount = user.legacy_reward_amount;
+        position.extra_reward = user.legacy_extra_reward;
+        position.reward_debt = user.legacy_reward_debt;
+        position.last_stake_time = user.legacy_last_stake_time;
+        position.lock_duration = user.legacy_lock_duration;
+        let lock_duration = position.lock_duration;
+        position.extra_percentage = extra_account.get_extra_reward_percentage(&lock_duration);
+        position.config_version = extra_account.version;
+
+        // rewards settled before the reward budget existed
+        let reward = position.reward_amount.checked_add(position.extra_reward).unwrap();
+        state.commit_reward(reward.try_into().unwrap());
+        user.legacy_reward_amount = 0;
+        user.legacy_extra_reward = 0;
+        user.legacy_reward_debt = 0;
+        user.legacy_last_stake_time = 0;
+        user.legacy_lock_duration = 0;
+        user.position_count = 1;
+        emit!(UserMigrated {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount: position.amount
+        });
+        Ok(())
+    }
+
+    // accounts of the first version are too small to load, they are migrated in two steps:
+    // begin_migrate_* saves the legacy data & frees the address, finish_migrate_* creates
+    // the account again at the same address with the current size, paid by the caller
+    pub fn begin_migrate_state(_ctx: Context<BeginMigration>, bump: u8) -> ProgramResult {
+        begin_migration(
+            _ctx.accounts,
+            _ctx.program_id,
+            bump,
+            LEGACY_STATE_SIZE,
+            StateAccount::discriminator(),
+            8,
+        )
+    }
+
+    pub fn begin_migrate_pool(_ctx: Context<BeginMigration>, bump: u8) -> ProgramResult {
+        begin_migration(
+            _ctx.accounts,
+            _ctx.program_id,
+            bump,
+            LEGACY_POOL_SIZE,
+            FarmPoolAccount::discriminator(),
+            9,
+        )
+    }
+
+    pub fn begin_migrate_extra_reward_configs(_ctx: Context<BeginMigration>, bump: u8) -> ProgramResult {
+        begin_migration(
+            _ctx.accounts,
+            _ctx.program_id,
+            bump,
+            LEGACY_EXTRA_REWARDS_SIZE,
+            ExtraRewardsAccount::discriminator(),
+            9,
+        )
+    }
+
+    // the state comes back paused, its pools are migrated next & the authority resumes
+    pub fn finish_migrate_state(_ctx: Context<FinishMigrateState>, bump: u8, state_id: u64) -> ProgramResult {
+        let legacy = LegacyStateAccount::deserialize(&mut _ctx.accounts.migration.data.as_slice())?;
+        require!(legacy.reward_vault == _ctx.accounts.reward_vault.key(), ErrorCode::InvalidMigration);
+        let state = &mut _ctx.accounts.state.load_init()?;
+        state.authority = legacy.authority;
+        state.reward_mint = legacy.reward_mint;
+        state.reward_vault = legacy.reward_vault;
+        state.bump = bump;
+        state.state_id = state_id;
+        state.start_time = legacy.start_time;
+        state.token_per_second = legacy.token_per_second;
+        // the first version had no budget, the vault balance is what is left of it
+        state.total_funded = _ctx.accounts.reward_vault.amount;
+        state.last_rate_change_time = _ctx.accounts.clock.unix_timestamp;
+        // total_point starts at 0, finish_migrate_pool adds the points back
+        state.paused = 1;
+        state.unstake_paused = 1;
+        emit!(AccountMigrated {
+            account: _ctx.accounts.state.key(),
+            authority: _ctx.accounts.authority.key()
+        });
+        Ok(())
+    }
+
+    pub fn finish_migrate_pool(_ctx: Context<FinishMigratePool>, bump: u8, pool_id: u64) -> ProgramResult {
+        let legacy = LegacyFarmPoolAccount::deserialize(&mut _ctx.accounts.migration.data.as_slice())?;
+        require!(legacy.mint == _ctx.accounts.mint.key(), ErrorCode::InvalidMigration);
+        let mut state = _ctx.accounts.state.load_mut()?;
+        // no emission until every pool has its point back
+        require!(state.paused == 1, ErrorCode::NotPaused);
+        let pool = &mut _ctx.accounts.pool.load_init()?;
+        pool.bump = bump;
+        pool.authority = legacy.authority;
+        pool.amount = legacy.amount;
+        pool.mint = legacy.mint;
+        pool.vault = legacy.vault;
+        pool.point = legacy.point;
+        pool.last_reward_time = legacy.last_reward_time;
+        pool.acc_reward_per_share = legacy.acc_reward_per_share;
+        pool.amount_multipler = legacy.amount_multipler;
+        pool.total_user = legacy.total_user;
+        pool.pool_id = pool_id;
+        pool.state = _ctx.accounts.state.key();
+        pool.state_id = state.state_id;
+        state.total_point = state.total_point.checked_add(pool.share_point()).unwrap();
+        _ctx.accounts.registry.add_pool(_ctx.accounts.pool.key())?;
+        emit!(AccountMigrated {
+            account: _ctx.accounts.pool.key(),
+            authority: _ctx.accounts.authority.key()
+        });
+        Ok(())
+    }
+
+    // the configs are kept as they were, the penalty starts disabled
+    pub fn finish_migrate_extra_reward_configs(
+        _ctx: Context<FinishMigrateExtraRewardConfigs>,
+        bump: u8,
+    ) -> ProgramResult {
+        let legacy = LegacyExtraRewardsAccount::deserialize(&mut _ctx.accounts.migration.data.as_slice())?;
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        extra_account.bump = bump;
+        extra_account.authority = legacy.authority;
+        extra_account.configs = legacy.configs;
+        extra_account.state = _ctx.accounts.state.key();
+        extra_account.version = 1;
+        emit!(AccountMigrated {
+            account: _ctx.accounts.extra_reward_account.key(),
+            authority: _ctx.accounts.authority.key()
+        });
+        Ok(())
+    }
+
+    // creates an account in a first version layout, for the migration tests only
+    pub fn write_legacy_fixture(
+        _ctx: Context<WriteLegacyFixture>,
+        seeds: Vec<Vec<u8>>,
+        bump: u8,
+        data: Vec<u8>,
+    ) -> ProgramResult {
+        write_legacy_fixture_account(_ctx.accounts, _ctx.program_id, seeds, bump, data)
+    }
+
+    pub fn stake(_ctx: Context<Stake>, amount: u64, lock_duration: i64) -> ProgramResult {
+        msg!("staking...");
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let mut position = _ctx.accounts.position.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        msg!("loaded states");
+        pool.assert_active(&state)?;
+        extra_account.validate_lock_duration(&lock_duration)?;
+        msg!("passed validate_lock_duration");
+        require!(
+            lock_duration >= position.lock_duration,
+            ErrorCode::InvalidLockDuration
+        );
+        msg!("passed lock_duration >= position.lock_duration");
+
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        let streams = pool.update_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts, _ctx.accounts.clock.unix_timestamp)?;
+        msg!("updated state");
+        let extra_percentage = position.get_extra_percentage(extra_account, _ctx.accounts.clock.unix_timestamp);
+        position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+        position.calculate_stream_rewards(&streams)?;
+        let penalty_share = position.claim_penalty_share(&pool)?;
+        user.amount = user.amount.checked_add(penalty_share).unwrap();
+        pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+        msg!("calculate_reward_amount");
+        position.amount = position.amount.checked_add(amount).unwrap();
+        user.amount = user.amount.checked_add(amount).unwrap();
+        pool.amount = pool.amount.checked_add(amount).unwrap();
+
+        position.start_lock(extra_account, lock_duration, _ctx.accounts.clock.unix_timestamp);
+        position.calculate_reward_debt(&pool)?;
+        position.calculate_stream_reward_debts(&streams)?;
+        msg!("calculate_reward_debt");
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.user_vault.to_account_info(),
+            to: _ctx.accounts.pool_vault.to_account_info(),
+            authority: _ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+        msg!("staked {}", amount);
+        emit!(UserStaked {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount,
+            lock_duration
+        });
+        Ok(())
+    }
+
+    // stakes the funder tokens into a new position of the beneficiary,
+    // a lock set here can't be left with early_unstake
+    pub fn stake_for(
+        _ctx: Context<StakeFor>,
+        bump: u8,
+        amount: u64,
+        lock_duration: i64,
+    ) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let position = &mut _ctx.accounts.position.load_init()?;
+        let now = _ctx.accounts.clock.unix_timestamp;
+
+        pool.assert_active(&state)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!user.is_legacy(), ErrorCode::MigrationRequired);
+        extra_account.validate_lock_duration(&lock_duration)?;
+        position.bump = bump;
+        position.pool = _ctx.accounts.pool.key();
+        position.user = _ctx.accounts.user.key();
+        position.authority = user.authority;
+        position.index = user.position_count;
+        user.position_count = user.position_count.checked_add(1).unwrap();
+
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        let streams = pool.update_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts, now)?;
+        position.amount = amount;
+        user.amount = user.amount.checked_add(amount).unwrap();
+        pool.amount = pool.amount.checked_add(amount).unwrap();
+        position.start_lock(extra_account, lock_duration, now);
+        if lock_duration > 0 {
+            position.funder_locked = 1;
+        }
+        position.calculate_reward_debt(&pool)?;
+        position.calculate_stream_reward_debts(&streams)?;
+
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.funder_vault.to_account_info(),
+            to: _ctx.accounts.pool_vault.to_account_info(),
+            authority: _ctx.accounts.funder.to_account_info(),
+        };
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+        emit!(PositionCreated {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: user.authority,
+            index: position.index,
+        });
+        emit!(UserStakedFor {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            funder: _ctx.accounts.funder.key(),
+            beneficiary: user.authority,
+            amount,
+            lock_duration
+        });
+        Ok(())
+    }
+
+    pub fn unstake(_ctx: Context<Stake>, amount: u64) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let mut position = _ctx.accounts.position.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+
+        pool.assert_unstake_active(&state)?;
+        require!(position.amount >= amount, ErrorCode::UnstakeOverAmount);
+        require!(pool.unbonding_period == 0, ErrorCode::UnbondingRequired);
+        require!(
+            position.last_stake_time
+                .checked_add(position.lock_duration)
+                .unwrap()
+                <= _ctx.accounts.clock.unix_timestamp,
+            ErrorCode::UnderLocked
+        );
+
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        let streams = pool.update_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts, _ctx.accounts.clock.unix_timestamp)?;
+        let extra_percentage = position.get_extra_percentage(extra_account, _ctx.accounts.clock.unix_timestamp);
+        position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+        position.calculate_stream_rewards(&streams)?;
+        let penalty_share = position.claim_penalty_share(&pool)?;
+        user.amount = user.amount.checked_add(penalty_share).unwrap();
+        pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+
+        // the lock has passed, so a funder lock ends here and the relock is the user's own
+        position.funder_locked = 0;
+        position.last_stake_time = _ctx.accounts.clock.unix_timestamp;
+        position.amount = position.amount.checked_sub(amount).unwrap();
+        user.amount = user.amount.checked_sub(amount).unwrap();
+        pool.amount = pool.amount.checked_sub(amount).unwrap();
+
+        if position.amount == 0
+        {
+            position.lock_duration = 0;
+        }
+
+        position.calculate_reward_debt(&pool)?;
+        position.calculate_stream_reward_debts(&streams)?;
+        drop(pool);
+
+        let new_pool = _ctx.accounts.pool.load()?;
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.pool_vault.to_account_info(),
+            to: _ctx.accounts.user_vault.to_account_info(),
+            authority: _ctx.accounts.pool.to_account_info(),
+        };
+
+        let pool_id_seed = new_pool.get_pool_id_seed();
+        let state_seed = new_pool.get_state_seed();
+        let seeds = &[new_pool.mint.as_ref(), pool_id_seed.as_ref(), state_seed.as_ref(), &[new_pool.bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        emit!(UserUnstaked {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount
+        });
+        Ok(())
+    }
+
+    pub fn request_unstake(_ctx: Context<Stake>, amount: u64) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let mut position = _ctx.accounts.position.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let now = _ctx.accounts.clock.unix_timestamp;
+
+        pool.assert_unstake_active(&state)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(position.amount >= amount, ErrorCode::UnstakeOverAmount);
+        require!(pool.unbonding_period > 0, ErrorCode::UnbondingNotRequired);
+        require!(
+            position.last_stake_time
+                .checked_add(position.lock_duration)
+                .unwrap()
+                <= now,
+            ErrorCode::UnderLocked
+        );
+
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        let streams = pool.update_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts, now)?;
+        let extra_percentage = position.get_extra_percentage(extra_account, now);
+        position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+        position.calculate_stream_rewards(&streams)?;
+        let penalty_share = position.claim_penalty_share(&pool)?;
+        user.amount = user.amount.checked_add(penalty_share).unwrap();
+        pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+
+        // unbonding tokens leave the pool right away and stop earning,
+        // the lock of the remaining stake is left as it is but a funder lock has passed
+        position.funder_locked = 0;
+        position.amount = position.amount.checked_sub(amount).unwrap();
+        user.amount = user.amount.checked_sub(amount).unwrap();
+        pool.amount = pool.amount.checked_sub(amount).unwrap();
+        let unbonded = position.add_unbonding(amount, pool.unbonding_period, now);
+
+        if position.amount == 0
+        {
+            position.lock_duration = 0;
+        }
+
+        position.calculate_reward_debt(&pool)?;
+        position.calculate_stream_reward_debts(&streams)?;
+        drop(pool);
+
+        if unbonded > 0 {
+            let new_pool = _ctx.accounts.pool.load()?;
+            let cpi_accounts = Transfer {
+                from: _ctx.accounts.pool_vault.to_account_info(),
+                to: _ctx.accounts.user_vault.to_account_info(),
+                authority: _ctx.accounts.pool.to_account_info(),
+            };
+
+            let pool_id_seed = new_pool.get_pool_id_seed();
+            let state_seed = new_pool.get_state_seed();
+            let seeds = &[new_pool.mint.as_ref(), pool_id_seed.as_ref(), state_seed.as_ref(), &[new_pool.bump]];
+            let signer = &[&seeds[..]];
+            let cpi_program = _ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, unbonded)?;
+            emit!(UserUnbondedWithdrawn {
+                pool: _ctx.accounts.pool.key(),
+                user: _ctx.accounts.user.key(),
+                position: _ctx.accounts.position.key(),
+                authority: _ctx.accounts.authority.key(),
+                amount: unbonded
+            });
+        }
+        emit!(UserUnstakeRequested {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount,
+            unbonding_end: position.unbonding_end
+        });
+        Ok(())
+    }
+
+    pub fn withdraw_unbonded(_ctx: Context<Stake>) -> ProgramResult {
+        let mut position = _ctx.accounts.position.load_mut()?;
+        let pool = _ctx.accounts.pool.load()?;
+
+        pool.assert_unstake_active(&_ctx.accounts.state.load()?)?;
+        let amount = position.unbonding_amount;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            position.unbonding_end <= _ctx.accounts.clock.unix_timestamp,
+            ErrorCode::StillUnbonding
+        );
+        position.unbonding_amount = 0;
+        position.unbonding_end = 0;
+
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.pool_vault.to_account_info(),
+            to: _ctx.accounts.user_vault.to_account_info(),
+            authority: _ctx.accounts.pool.to_account_info(),
+        };
+
+        let pool_id_seed = pool.get_pool_id_seed();
+        let state_seed = pool.get_state_seed();
+        let seeds = &[pool.mint.as_ref(), pool_id_seed.as_ref(), state_seed.as_ref(), &[pool.bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        emit!(UserUnbondedWithdrawn {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount
+        });
+        Ok(())
+    }
+
+    // returns the principal without the reward math, pending rewards are forfeited
+    pub fn emergency_withdraw(_ctx: Context<Stake>) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let mut position = _ctx.accounts.position.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let now = _ctx.accounts.clock.unix_timestamp;
+
+        // the lock only gives way once the admin unlocks the pool, never for a funder lock
+        if pool.emergency_unlocked == 0 || position.funder_locked == 1 {
+            require!(
+                position.last_stake_time.saturating_add(position.lock_duration) <= now,
+                ErrorCode::UnderLocked
+            );
+        }
+        // the cooldown gives way with the same unlock, the staked principal goes through request_unstake otherwise
+        if pool.emergency_unlocked == 0 {
+            require!(
+                pool.unbonding_period == 0 || position.amount == 0,
+                ErrorCode::UnbondingRequired
+            );
+            require!(position.unbonding_end <= now, ErrorCode::StillUnbonding);
+        }
+
+        let penalty_share = position.get_penalty_share_unchecked(&pool);
+        let staked = position.amount.saturating_add(penalty_share);
+        let amount = staked.saturating_add(position.unbonding_amount);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // the streams aren't settled, the forfeited part of each goes back to its own budget
+        let streams = pool.load_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts)?;
+        let forfeited_reward = position.forfeit_rewards(&pool, &streams)?;
+        state.release_reward(forfeited_reward);
+
+        user.amount = user.amount.saturating_sub(position.amount);
+        pool.amount = pool.amount.saturating_sub(position.amount);
+        position.amount = 0;
+        position.lock_duration = 0;
+        position.funder_locked = 0;
+        position.unbonding_amount = 0;
+        position.unbonding_end = 0;
+        position.reward_debt = 0;
+        position.penalty_debt = 0;
+        drop(pool);
+
+        let new_pool = _ctx.accounts.pool.load()?;
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.pool_vault.to_account_info(),
+            to: _ctx.accounts.user_vault.to_account_info(),
+            authority: _ctx.accounts.pool.to_account_info(),
+        };
+
+        let pool_id_seed = new_pool.get_pool_id_seed();
+        let state_seed = new_pool.get_state_seed();
+        let seeds = &[new_pool.mint.as_ref(), pool_id_seed.as_ref(), state_seed.as_ref(), &[new_pool.bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        emit!(UserEmergencyWithdrawn {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount,
+            forfeited_reward
+        });
+        Ok(())
+    }
+
+    pub fn early_unstake(_ctx: Context<EarlyUnstake>, amount: u64) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+        let mut position = _ctx.accounts.position.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let now = _ctx.accounts.clock.unix_timestamp;
+
+        pool.assert_unstake_active(&state)?;
+        require!(
+            position.penalty_enabled == 1 && position.funder_locked == 0,
+            ErrorCode::UnderLocked
+        );
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(position.amount >= amount, ErrorCode::UnstakeOverAmount);
+        let elapsed = now.checked_sub(position.last_stake_time).unwrap();
+        let lock_duration = position.lock_duration;
+        require!(elapsed < lock_duration, ErrorCode::NotUnderLocked);
+
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        let streams = pool.update_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts, now)?;
+        let extra_percentage = position.get_extra_percentage(extra_account, now);
+        position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+        position.calculate_stream_rewards(&streams)?;
+        let penalty_share = position.claim_penalty_share(&pool)?;
+        user.amount = user.amount.checked_add(penalty_share).unwrap();
+        pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+
+        let principal_penalty: u64 = u128::from(amount)
+            .checked_mul(u128::from(position.get_penalty_percentage(
+                position.penalty_principal_percentage,
+                elapsed,
+            )))
+            .unwrap()
+            .checked_div(u128::from(FULL_100))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let extra_reward_penalty: u128 = position
+            .extra_reward
+            .checked_mul(u128::from(amount))
+            .unwrap()
+            .checked_div(u128::from(position.amount))
+            .unwrap()
+            .checked_mul(u128::from(position.get_penalty_percentage(
+                position.penalty_extra_reward_percentage,
+                elapsed,
+            )))
+            .unwrap()
+            .checked_div(u128::from(FULL_100))
+            .unwrap();
+        position.extra_reward = position.extra_reward.checked_sub(extra_reward_penalty).unwrap();
+        // the forfeited extra reward was never paid out, it goes back to the budget whatever the destination
+        state.release_reward(extra_reward_penalty.try_into().unwrap());
+
+        position.amount = position.amount.checked_sub(amount).unwrap();
+        user.amount = user.amount.checked_sub(amount).unwrap();
+        pool.amount = pool.amount.checked_sub(amount).unwrap();
+
+        // the treasury address is read live, the destination is the one locked in;
+        // without stakers left to redistribute to it goes to the treasury, or is burned without one
+        let treasury = extra_account.penalty.treasury;
+        let mut destination = position.penalty_destination;
+        if destination == PENALTY_REDISTRIBUTE && pool.amount == 0 {
+            destination = PENALTY_TO_TREASURY;
+            if treasury == Pubkey::default() {
+                destination = PENALTY_BURN;
+            }
+        }
+        if destination == PENALTY_REDISTRIBUTE {
+            pool.acc_penalty_per_share = pool
+                .acc_penalty_per_share
+                .checked_add(
+                    u128::from(principal_penalty)
+                        .checked_mul(ACC_PRECISION)
+                        .unwrap()
+                        .checked_div(u128::from(pool.amount))
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        if position.amount == 0
+        {
+            position.lock_duration = 0;
+            position.funder_locked = 0;
+        }
+
+        // without a cooldown the rest goes out now, otherwise only what has cooled down already
+        let mut payout = amount.checked_sub(principal_penalty).unwrap();
+        if pool.unbonding_period > 0 {
+            payout = position.add_unbonding(payout, pool.unbonding_period, now);
+        }
+
+        position.calculate_reward_debt(&pool)?;
+        position.calculate_stream_reward_debts(&streams)?;
+        drop(pool);
+
+        let new_pool = _ctx.accounts.pool.load()?;
+        let pool_id_seed = new_pool.get_pool_id_seed();
+        let state_seed = new_pool.get_state_seed();
+        let seeds = &[new_pool.mint.as_ref(), pool_id_seed.as_ref(), state_seed.as_ref(), &[new_pool.bump]];
+        let signer = &[&seeds[..]];
+
+        if payout > 0 {
+            let cpi_accounts = Transfer {
+                from: _ctx.accounts.pool_vault.to_account_info(),
+                to: _ctx.accounts.user_vault.to_account_info(),
+                authority: _ctx.accounts.pool.to_account_info(),
+            };
+            let cpi_program = _ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, payout)?;
+        }
+
+        if principal_penalty > 0 && destination == PENALTY_TO_TREASURY {
+            let treasury_vault = Account::<TokenAccount>::try_from(&_ctx.accounts.treasury_vault)?;
+            require!(
+                treasury_vault.owner == treasury && treasury_vault.mint == new_pool.mint,
+                ErrorCode::InvalidPenalty
+            );
+            let cpi_accounts = Transfer {
+                from: _ctx.accounts.pool_vault.to_account_info(),
+                to: _ctx.accounts.treasury_vault.to_account_info(),
+                authority: _ctx.accounts.pool.to_account_info(),
+            };
+            let cpi_program = _ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, principal_penalty)?;
+        }
+        if principal_penalty > 0 && destination == PENALTY_BURN {
+            let cpi_accounts = Burn {
+                mint: _ctx.accounts.mint.to_account_info(),
+                to: _ctx.accounts.pool_vault.to_account_info(),
+                authority: _ctx.accounts.pool.to_account_info(),
+            };
+            let cpi_program = _ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::burn(cpi_ctx, principal_penalty)?;
+        }
+
+        emit!(UserEarlyUnstaked {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount,
+            principal_penalty,
+            extra_reward_penalty: extra_reward_penalty.try_into().unwrap(),
+            destination
+        });
+        Ok(())
+    }
+
+    pub fn harvest(_ctx: Context<Harvest>) -> ProgramResult {
+        let authority = _ctx.accounts.authority.key();
+        harvest_position(
+            &_ctx.accounts.state,
+            &_ctx.accounts.pool,
+            &_ctx.accounts.user,
+            &_ctx.accounts.position,
+            &_ctx.accounts.extra_reward_account,
+            &_ctx.accounts.reward_vault,
+            &_ctx.accounts.user_vault,
+            None,
+            &_ctx.accounts.token_program,
+            &_ctx.accounts.clock,
+            _ctx.program_id,
+            _ctx.remaining_accounts,
+            &authority,
+            Some(authority),
+            None,
+        )
+    }
+
+    pub fn harvest_vesting(_ctx: Context<HarvestVesting>) -> ProgramResult {
+        let authority = _ctx.accounts.authority.key();
+        harvest_position(
+            &_ctx.accounts.state,
+            &_ctx.accounts.pool,
+            &_ctx.accounts.user,
+            &_ctx.accounts.position,
+            &_ctx.accounts.extra_reward_account,
+            &_ctx.accounts.reward_vault,
+            &_ctx.accounts.user_vault,
+            None,
+            &_ctx.accounts.token_program,
+            &_ctx.accounts.clock,
+            _ctx.program_id,
+            _ctx.remaining_accounts,
+            &authority,
+            Some(authority),
+            Some(&_ctx.accounts.vesting),
+        )
+    }
+
+    pub fn create_vesting(_ctx: Context<CreateVesting>, bump: u8) -> ProgramResult {
+        let mut vesting = _ctx.accounts.vesting.load_init()?;
+        vesting.bump = bump;
+        vesting.user = _ctx.accounts.user.key();
+        vesting.authority = _ctx.accounts.authority.key();
+        vesting.pool = _ctx.accounts.pool.key();
+        Ok(())
+    }
+
+    // early claims take the unvested rest too, minus the pool penalty on it
+    pub fn claim_vested(_ctx: Context<ClaimVested>, early: bool) -> ProgramResult {
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let pool = _ctx.accounts.pool.load()?;
+        let mut vesting = _ctx.accounts.vesting.load_mut()?;
+        let now = _ctx.accounts.clock.unix_timestamp;
+
+        let claimable = vesting.get_claimable(now);
+        let mut amount = claimable;
+        let mut penalty = 0;
+        if early {
+            let unvested = vesting.amount.checked_sub(claimable).unwrap();
+            penalty = u128::from(unvested)
+                .checked_mul(u128::from(pool.vesting_early_claim_penalty))
+                .unwrap()
+                .checked_div(u128::from(FULL_100))
+                .unwrap()
+                .try_into()
+                .unwrap();
+            amount = vesting.amount.checked_sub(penalty).unwrap();
+        }
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        vesting.claim(claimable, now);
+        if early {
+            vesting.amount = 0;
+        }
+        // forfeited rewards go back to the budget
+        state.release_reward(penalty);
+        state.distribute_reward(amount);
+        let state_bump = state.bump;
+        let state_id_seed = state.get_state_id_seed();
+        drop(state);
+
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.reward_vault.to_account_info(),
+            to: _ctx.accounts.user_vault.to_account_info(),
+            authority: _ctx.accounts.state.to_account_info(),
+        };
+        let seeds = &[b"state".as_ref(), state_id_seed.as_ref(), &[state_bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        emit!(VestedClaimed {
+            pool: _ctx.accounts.pool.key(),
+            vesting: _ctx.accounts.vesting.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount,
+            penalty
+        });
+        Ok(())
+    }
+
+    // the authority or its delegate claims to any token account
+    pub fn harvest_to(_ctx: Context<HarvestTo>) -> ProgramResult {
+        harvest_position(
+            &_ctx.accounts.state,
+            &_ctx.accounts.pool,
+            &_ctx.accounts.user,
+            &_ctx.accounts.position,
+            &_ctx.accounts.extra_reward_account,
+            &_ctx.accounts.reward_vault,
+            &_ctx.accounts.recipient_vault,
+            None,
+            &_ctx.accounts.token_program,
+            &_ctx.accounts.clock,
+            _ctx.program_id,
+            _ctx.remaining_accounts,
+            &_ctx.accounts.claimer.key(),
+            None,
+            None,
+        )
+    }
+
+    pub fn set_delegate(_ctx: Context<ChangeUserSetting>, delegate: Pubkey) -> ProgramResult {
+        let mut user = _ctx.accounts.user.load_mut()?;
+        user.delegate = delegate;
+        emit!(DelegateChanged {
+            user: _ctx.accounts.user.key(),
+            delegate
+        });
+        Ok(())
+    }
+
+    pub fn compound(_ctx: Context<Compound>) -> ProgramResult {
+        let (amount, _) = compound_position(
+            &_ctx.accounts.state,
+            &_ctx.accounts.pool,
+            &_ctx.accounts.user,
+            &_ctx.accounts.position,
+            &_ctx.accounts.extra_reward_account,
+            &_ctx.accounts.reward_vault,
+            &_ctx.accounts.pool_vault,
+            None,
+            &_ctx.accounts.token_program,
+            &_ctx.accounts.clock,
+            _ctx.program_id,
+            _ctx.remaining_accounts,
+        )?;
+        emit!(UserCompounded {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.authority.key(),
+            amount
+        });
+        Ok(())
+    }
+
+    pub fn set_auto_compound(_ctx: Context<ChangeUserSetting>, auto_compound: bool) -> ProgramResult {
+        let mut user = _ctx.accounts.user.load_mut()?;
+        user.auto_compound = auto_compound as u8;
+        emit!(AutoCompoundChanged {
+            user: _ctx.accounts.user.key(),
+            auto_compound
+        });
+        Ok(())
+    }
+
+    // anyone can compound for users who opted in
+    pub fn crank_compound(_ctx: Context<CrankCompound>) -> ProgramResult {
+        require!(_ctx.accounts.user.load()?.auto_compound == 1, ErrorCode::AutoCompoundDisabled);
+        let (amount, keeper_tip) = compound_position(
+            &_ctx.accounts.state,
+            &_ctx.accounts.pool,
+            &_ctx.accounts.user,
+            &_ctx.accounts.position,
+            &_ctx.accounts.extra_reward_account,
+            &_ctx.accounts.reward_vault,
+            &_ctx.accounts.pool_vault,
+            Some(&*_ctx.accounts.keeper_vault),
+            &_ctx.accounts.token_program,
+            &_ctx.accounts.clock,
+            _ctx.program_id,
+            _ctx.remaining_accounts,
+        )?;
+        emit!(UserCompounded {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            position: _ctx.accounts.position.key(),
+            authority: _ctx.accounts.keeper.key(),
+            amount
+        });
+        emit!(KeeperTipPaid {
+            pool: _ctx.accounts.pool.key(),
+            position: _ctx.accounts.position.key(),
+            keeper: _ctx.accounts.keeper.key(),
+            amount: keeper_tip
+        });
+        Ok(())
+    }
+
+    pub fn set_auto_harvest(_ctx: Context<ChangeUserSetting>, auto_harvest: bool) -> ProgramResult {
+        let mut user = _ctx.accounts.user.load_mut()?;
+        user.auto_harvest = auto_harvest as u8;
+        emit!(AutoHarvestChanged {
+            user: _ctx.accounts.user.key(),
+            auto_harvest
+        });
+        Ok(())
+    }
+
+    // anyone can harvest for users who opted in, stream rewards only go to the user
+    pub fn crank_harvest(_ctx: Context<CrankHarvest>) -> ProgramResult {
+        let user = _ctx.accounts.user.load()?;
+        require!(user.auto_harvest == 1, ErrorCode::AutoHarvestDisabled);
+        let owner = user.authority;
+        drop(user);
+        harvest_position(
+            &_ctx.accounts.state,
+            &_ctx.accounts.pool,
+            &_ctx.accounts.user,
+            &_ctx.accounts.position,
+            &_ctx.accounts.extra_reward_account,
+            &_ctx.accounts.reward_vault,
+            &_ctx.accounts.user_vault,
+            Some(&*_ctx.accounts.keeper_vault),
+            &_ctx.accounts.token_program,
+            &_ctx.accounts.clock,
+            _ctx.program_id,
+            _ctx.remaining_accounts,
+            &_ctx.accounts.keeper.key(),
+            Some(owner),
+            None,
+        )
+    }
+
+    pub fn harvest_all(_ctx: Context<HarvestAll>) -> ProgramResult {
+        let extra_account = &mut _ctx.accounts.extra_reward_account;
+        let mut state = _ctx.accounts.state.load_mut()?;
+        let mut pool = _ctx.accounts.pool.load_mut()?;
+        let mut user = _ctx.accounts.user.load_mut()?;
+
+        pool.assert_active(&state)?;
+        require!(pool.vesting_mode == VESTING_NONE, ErrorCode::VestingRequired);
+        pool.update(&mut state, &_ctx.accounts.clock)?;
+        // stream rewards are settled into each position but only paid by harvest
+        let streams = pool.update_streams(&_ctx.accounts.pool.key(), _ctx.program_id, _ctx.remaining_accounts, _ctx.accounts.clock.unix_timestamp)?;
+        let positions = &_ctx.remaining_accounts[streams.len()..];
+
+        let mut total_reward: u128 = 0;
+        for position_info in positions.iter() {
+            let loader = Loader::<FarmPoolPositionAccount>::try_from(&_ctx.program_id, &position_info)?;
+            let mut position = loader.load_mut()?;
+            require!(position.user == _ctx.accounts.user.key(), ErrorCode::InvalidPosition);
+            require!(position.pool == _ctx.accounts.pool.key(), ErrorCode::InvalidPosition);
+
+            let extra_percentage = position.get_extra_percentage(extra_account, _ctx.accounts.clock.unix_timestamp);
+            position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+            position.calculate_stream_rewards(&streams)?;
+            let penalty_share = position.claim_penalty_share(&pool)?;
+            user.amount = user.amount.checked_add(penalty_share).unwrap();
+            pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+            total_reward = total_reward
+                .checked_add(position.reward_amount)
+                .unwrap()
+                .checked_add(position.extra_reward)
+                .unwrap();
+
+            position.reward_amount = 0;
+            position.extra_reward = 0;
+            position.calculate_reward_debt(&pool)?;
+            position.calculate_stream_reward_debts(&streams)?;
+        }
+        let total_reward: u64 = total_reward.try_into().unwrap();
+        state.distribute_reward(total_reward);
+        let state_bump = state.bump;
+        let state_id_seed = state.get_state_id_seed();
+        drop(state);
+
+        let cpi_accounts = Transfer {
+            from: _ctx.accounts.reward_vault.to_account_info(),
+            to: _ctx.accounts.user_vault.to_account_info(),
+            authority: _ctx.accounts.state.to_account_info(),
+        };
+
+        let seeds = &[b"state".as_ref(), state_id_seed.as_ref(), &[state_bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = _ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, total_reward)?;
+
+        emit!(UserHarvestedAll {
+            pool: _ctx.accounts.pool.key(),
+            user: _ctx.accounts.user.key(),
+            authority: _ctx.accounts.authority.key(),
+            positions: positions.len() as u64,
+            amount: total_reward
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8, state_id: u64)]
+pub struct CreateState<'info> {
+    #[account(
+        init,
+        seeds = [b"state".as_ref(), get_id_seed(state_id).as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<StateAccount>()
+    )]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Account<'info, TokenAccount>,
+    pub reward_mint: Box<Account<'info, Mint>>,
+    pub migration: AccountInfo<'info>, // [b"migration", state], empty unless a migration is pending
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct BeginMigration<'info> {
+    #[account(mut)]
+    pub legacy: AccountInfo<'info>,
+    #[account(init, seeds = [b"migration".as_ref(), legacy.key().as_ref()], bump = bump, payer = authority, space = 8 + 69 + 197)]
+    pub migration: Box<Account<'info, MigrationAccount>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8, state_id: u64)]
+pub struct FinishMigrateState<'info> {
+    #[account(
+        init,
+        seeds = [b"state".as_ref(), get_id_seed(state_id).as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<StateAccount>()
+    )]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [b"migration".as_ref(), state.key().as_ref()], bump = migration.bump, has_one = authority, close = authority)]
+    pub migration: Box<Account<'info, MigrationAccount>>,
+    #[account(constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8, pool_id: u64)]
+pub struct FinishMigratePool<'info> {
+    #[account(
+        init,
+        seeds = [mint.key().as_ref(), get_id_seed(pool_id).as_ref(), get_state_seed(&state.key(), state.load()?.state_id).as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<FarmPoolAccount>()
+    )]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    #[account(mut, seeds = [b"migration".as_ref(), pool.key().as_ref()], bump = migration.bump, has_one = authority, close = authority)]
+    pub migration: Box<Account<'info, MigrationAccount>>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct FinishMigrateExtraRewardConfigs<'info> {
+    #[account(init, seeds = [b"extra".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = bump, payer = authority, space = 8 + 197 + 51 + 32 + 8 + 32 + 32)]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [b"migration".as_ref(), extra_reward_account.key().as_ref()], bump = migration.bump, has_one = authority, close = authority)]
+    pub migration: Box<Account<'info, MigrationAccount>>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WriteLegacyFixture<'info> {
+    #[account(mut)]
+    pub fixture: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct CreatePoolRegistry<'info> {
+    #[account(init, seeds = [b"registry".as_ref(), state.key().as_ref()], bump = bump, payer = authority, space = 8 + 5 + 32 * 32)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterPool<'info> {
+    #[account(mut, seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Fund<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, constraint = state.load()?.is_funder(&authority.key()))]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct GetRewardRunway<'info> {
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeTokensPerSecond<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, constraint = state.load()?.is_operator(&authority.key()))]
+    pub state: Loader<'info, StateAccount>,
+    #[account(seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8, pool_id: u64)]
+pub struct CreateFarmPool<'info> {
+    #[account(
+        init,
+        seeds = [mint.key().as_ref(), get_id_seed(pool_id).as_ref(), get_state_seed(&state.key(), state.load()?.state_id).as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<FarmPoolAccount>()
+    )]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(constraint = vault.owner == pool.key())]
+    pub vault: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct CloseFarmPool<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state, has_one = authority, close = authority)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ChangePoolSetting<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state, constraint = pool.load()?.authority == authority.key() || state.load()?.is_operator(&authority.key()))]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+// terms the stakers rely on, kept from the operator
+#[derive(Accounts)]
+pub struct ChangePoolTerms<'info> {
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = authority)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct CreateRewardStream<'info> {
+    #[account(
+        init,
+        seeds = [b"stream".as_ref(), pool.key().as_ref(), pool.load()?.stream_count.to_le_bytes().as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<RewardStreamAccount>()
+    )]
+    pub stream: Loader<'info, RewardStreamAccount>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = authority)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(constraint = vault.owner == stream.key(), constraint = vault.mint == mint.key())]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewardStream<'info> {
+    #[account(mut, seeds = [b"stream".as_ref(), stream.load()?.pool.key().as_ref(), stream.load()?.index.to_le_bytes().as_ref()], bump = stream.load()?.bump)]
+    pub stream: Loader<'info, RewardStreamAccount>,
+    pub authority: Signer<'info>,
+    #[account(mut, constraint = stream_vault.key() == stream.load()?.vault)]
+    pub stream_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeRewardStream<'info> {
+    #[account(mut, seeds = [b"stream".as_ref(), pool.key().as_ref(), stream.load()?.index.to_le_bytes().as_ref()], bump = stream.load()?.bump, has_one = pool, has_one = authority)]
+    pub stream: Loader<'info, RewardStreamAccount>,
+    #[account(seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct CreateExtraRewardsConfigs<'info> {
+    #[account(init, seeds = [b"extra".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = bump, payer = authority, space = 8 + 197 + 51 + 32 + 8 + 32 + 32)]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct CreatePoolExtraRewardsConfigs<'info> {
+    #[account(init, seeds = [b"extra".as_ref(), pool.key().as_ref()], bump = bump, payer = authority, space = 8 + 197 + 51 + 32 + 8 + 32 + 32)]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state, has_one = authority)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetExtraRewardsConfigs<'info> {
+    #[account(
+        mut,
+        has_one = state,
+        constraint = extra_reward_account.authority == authority.key() || state.load()?.is_operator(&authority.key())
+    )]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// the penalty terms and treasury stay with the authority
+#[derive(Accounts)]
+pub struct SetEarlyUnstakePenalty<'info> {
+    #[account(mut, has_one = authority)]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, constraint = state.load()?.is_pauser(&authority.key()))]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolPaused<'info> {
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(
+        mut,
+        seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump,
+        has_one = state,
+        constraint = pool.load()?.authority == authority.key() || state.load()?.is_pauser(&authority.key())
+    )]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct CreateAdminQueue<'info> {
+    #[account(init, seeds = [b"queue".as_ref(), state.key().as_ref()], bump = bump, payer = authority, space = 8 + 45 + 402 * 8)]
+    pub queue: Box<Account<'info, AdminQueueAccount>>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct QueueChange<'info> {
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [b"queue".as_ref(), state.key().as_ref()], bump = queue.bump)]
+    pub queue: Box<Account<'info, AdminQueueAccount>>,
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteStateChange<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    #[account(mut, seeds = [b"queue".as_ref(), state.key().as_ref()], bump = queue.bump)]
+    pub queue: Box<Account<'info, AdminQueueAccount>>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ExecutePoolChange<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(seeds = [b"registry".as_ref(), state.key().as_ref()], bump = registry.bump)]
+    pub registry: Box<Account<'info, PoolRegistryAccount>>,
+    #[account(mut, seeds = [b"queue".as_ref(), state.key().as_ref()], bump = queue.bump)]
+    pub queue: Box<Account<'info, AdminQueueAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteExtraRewardsChange<'info> {
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [b"queue".as_ref(), state.key().as_ref()], bump = queue.bump)]
+    pub queue: Box<Account<'info, AdminQueueAccount>>,
+    #[account(mut, has_one = state)]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeStateAuthority<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub state: Loader<'info, StateAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptStateAuthority<'info> {
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump, constraint = state.load()?.pending_authority == pending_authority.key())]
+    pub state: Loader<'info, StateAccount>,
+    pub pending_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangePoolAuthority<'info> {
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = authority)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptPoolAuthority<'info> {
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, constraint = pool.load()?.pending_authority == pending_authority.key())]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub pending_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeExtraRewardsAuthority<'info> {
+    #[account(mut, has_one = authority)]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptExtraRewardsAuthority<'info> {
+    #[account(mut, constraint = extra_reward_account.pending_authority == pending_authority.key())]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    pub pending_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
 #[instruction(bump: u8)]
-pub struct CreateState<'info> {
+pub struct CreatePoolUser<'info> {
     #[account(
         init,
-        seeds = [b"state".as_ref()],
+        seeds = [pool.key().as_ref(), authority.key().as_ref()],
         bump = bump,
         payer = authority,
-        space = 8 + size_of::<StateAccount>()
+        space = 8 + size_of::<FarmPoolUserAccount>()
     )]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
-    #[account(constraint = reward_vault.owner == state.key())]
-    pub reward_vault: Account<'info, TokenAccount>,
-    pub reward_mint: Box<Account<'info, Mint>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
     #[account(constraint = token_program.key == &token::ID)]
     pub token_program: Program<'info, Token>,
-    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct Fund<'info> {
-    #[account(mut, seeds = [pool.load()?.mint.key().as_ref()], bump = pool.load()?.bump)]
-    pub pool: Loader<'info, FarmPoolAccount>,
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump)]
+#[instruction(bump: u8)]
+pub struct CreatePoolUserFor<'info> {
+    #[account(
+        init,
+        seeds = [pool.key().as_ref(), beneficiary.key().as_ref()],
+        bump = bump,
+        payer = funder,
+        space = 8 + size_of::<FarmPoolUserAccount>()
+    )]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub beneficiary: AccountInfo<'info>,
+    pub funder: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct CreateUserEtherAddress<'info> {
+    #[account(
+        init,
+        seeds = [b"ether".as_ref(), authority.key().as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<FarmUserEtherAddress>()
+    )]
+    pub user: Loader<'info, FarmUserEtherAddress>,
     pub authority: Signer<'info>,
-    #[account(mut, constraint = reward_vault.owner == state.key())]
-    pub reward_vault: Box<Account<'info, TokenAccount>>,
-    #[account(mut, constraint = user_vault.owner == authority.key())]
-    pub user_vault: Box<Account<'info, TokenAccount>>,
-    #[account(constraint = token_program.key == &token::ID)]
-    pub token_program: Program<'info, Token>,
-    pub clock: Sysvar<'info, Clock>,
-} 
+    pub system_program: Program<'info, System>,
+}
 
 #[derive(Accounts)]
-pub struct ChangeTokensPerSecond<'info> {
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump, has_one = authority)]
-    pub state: Loader<'info, StateAccount>,
+pub struct SetUserEtherAddress<'info> {
+    #[account(mut, seeds = [b"ether".as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = authority)]
+    pub user: Loader<'info, FarmUserEtherAddress>,
     pub authority: Signer<'info>,
-    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 #[instruction(bump: u8)]
-pub struct CreateFarmPool<'info> {
+pub struct CreatePosition<'info> {
     #[account(
         init,
-        seeds = [mint.key().as_ref()],
+        seeds = [user.key().as_ref(), user.load()?.position_count.to_le_bytes().as_ref()],
         bump = bump,
         payer = authority,
-        space = 8 + size_of::<FarmPoolAccount>()
+        space = 8 + size_of::<FarmPoolPositionAccount>()
     )]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump)]
     pub pool: Loader<'info, FarmPoolAccount>,
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump, has_one = authority)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct MigrateUser<'info> {
+    #[account(
+        init,
+        seeds = [user.key().as_ref(), 0u64.to_le_bytes().as_ref()],
+        bump = bump,
+        payer = authority,
+        space = 8 + size_of::<FarmPoolPositionAccount>()
+    )]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
-    pub mint: Box<Account<'info, Mint>>,
-    #[account(constraint = vault.owner == pool.key())]
-    pub vault: Account<'info, TokenAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
     pub authority: Signer<'info>,
+    #[account(constraint = mint.key() == pool.load()?.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, constraint = pool_vault.owner == pool.key())]
+    pub pool_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
     pub system_program: Program<'info, System>,
     #[account(constraint = token_program.key == &token::ID)]
     pub token_program: Program<'info, Token>,
@@ -437,129 +2501,267 @@ pub struct CreateFarmPool<'info> {
 }
 
 #[derive(Accounts)]
-pub struct CloseFarmPool<'info> {
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump, has_one = authority)]
+#[instruction(bump: u8)]
+pub struct StakeFor<'info> {
+    #[account(
+        init,
+        seeds = [user.key().as_ref(), user.load()?.position_count.to_le_bytes().as_ref()],
+        bump = bump,
+        payer = funder,
+        space = 8 + size_of::<FarmPoolPositionAccount>()
+    )]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [pool.key().as_ref(), user.load()?.authority.as_ref()], bump = user.load()?.bump, has_one = pool)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
-    #[account(mut, seeds = [pool.load()?.mint.key().as_ref()], bump = pool.load()?.bump, has_one = authority, close = authority)]
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
     pub pool: Loader<'info, FarmPoolAccount>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    pub funder: Signer<'info>,
+    #[account(mut, constraint = pool_vault.owner == pool.key())]
+    pub pool_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = funder_vault.owner == funder.key())]
+    pub funder_vault: Box<Account<'info, TokenAccount>>,
     pub system_program: Program<'info, System>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct ChangePoolSetting<'info> {
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump)]
+pub struct EarlyUnstake<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
-    #[account(mut, seeds = [pool.load()?.mint.key().as_ref()], bump = pool.load()?.bump, has_one = authority)]
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
     pub pool: Loader<'info, FarmPoolAccount>,
     pub authority: Signer<'info>,
+    #[account(mut, constraint = mint.key() == pool.load()?.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, constraint = pool_vault.owner == pool.key())]
+    pub pool_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
+    // only read when the penalty goes to the treasury, any account otherwise
+    #[account(mut)]
+    pub treasury_vault: AccountInfo<'info>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-#[instruction(bump: u8)]
-pub struct CreateExtraRewardsConfigs<'info> {
-    #[account(init, seeds = [b"extra".as_ref()], bump = bump, payer = authority, space = 8 + 197)]
+pub struct Harvest<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
     pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
     pub authority: Signer<'info>,
+    #[account(constraint = mint.key() == pool.load()?.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
     pub system_program: Program<'info, System>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct SetExtraRewardsConfigs<'info> {
-    #[account(mut, seeds = [b"extra".as_ref()], bump = extra_reward_account.bump, has_one = authority)]
+pub struct HarvestVesting<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"vesting".as_ref(), user.key().as_ref()], bump = vesting.load()?.bump, has_one = user)]
+    pub vesting: Loader<'info, VestingAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
     pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
     pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
 #[instruction(bump: u8)]
-pub struct CreatePoolUser<'info> {
+pub struct CreateVesting<'info> {
     #[account(
         init,
-        seeds = [pool.key().as_ref(), authority.key().as_ref()],
+        seeds = [b"vesting".as_ref(), user.key().as_ref()],
         bump = bump,
         payer = authority,
-        space = 8 + size_of::<FarmPoolUserAccount>()
+        space = 8 + size_of::<VestingAccount>()
     )]
+    pub vesting: Loader<'info, VestingAccount>,
+    #[account(seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
     pub user: Loader<'info, FarmPoolUserAccount>,
-    #[account(seeds = [b"state".as_ref()], bump = state.load()?.bump)]
-    pub state: Loader<'info, StateAccount>,
-    #[account(mut, seeds = [pool.load()?.mint.key().as_ref()], bump = pool.load()?.bump)]
+    #[account(seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump)]
     pub pool: Loader<'info, FarmPoolAccount>,
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(mut, seeds = [b"vesting".as_ref(), vesting.load()?.user.as_ref()], bump = vesting.load()?.bump, has_one = pool, has_one = authority)]
+    pub vesting: Loader<'info, VestingAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct HarvestTo<'info> {
+    #[account(
+        mut,
+        seeds = [pool.key().as_ref(), user.load()?.authority.as_ref()], bump = user.load()?.bump,
+        has_one = pool,
+        constraint = user.load()?.authority == claimer.key() || user.load()?.delegate == claimer.key()
+    )]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub claimer: Signer<'info>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = recipient_vault.mint == state.load()?.reward_mint)]
+    pub recipient_vault: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct HarvestAll<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = user_vault.owner == authority.key())]
+    pub user_vault: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_program.key == &token::ID)]
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct Compound<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
+    pub state: Loader<'info, StateAccount>,
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
+    pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
+    pub pool: Loader<'info, FarmPoolAccount>,
+    pub authority: Signer<'info>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = pool_vault.owner == pool.key())]
+    pub pool_vault: Box<Account<'info, TokenAccount>>,
     #[account(constraint = token_program.key == &token::ID)]
     pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-#[instruction(bump: u8)]
-pub struct CreateUserEtherAddress<'info> {
-    #[account(
-        init,
-        seeds = [b"ether".as_ref(), authority.key().as_ref()],
-        bump = bump,
-        payer = authority,
-        space = 8 + size_of::<FarmUserEtherAddress>()
-    )]
-    pub user: Loader<'info, FarmUserEtherAddress>,
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-pub struct SetUserEtherAddress<'info> {
-    #[account(mut, seeds = [b"ether".as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = authority)]
-    pub user: Loader<'info, FarmUserEtherAddress>,
+pub struct ChangeUserSetting<'info> {
+    #[account(mut, has_one = authority)]
+    pub user: Loader<'info, FarmPoolUserAccount>,
     pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Stake<'info> {
-    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+pub struct CrankCompound<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), user.load()?.authority.as_ref()], bump = user.load()?.bump, has_one = pool)]
     pub user: Loader<'info, FarmPoolUserAccount>,
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump)]
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
-    #[account(seeds = [b"extra".as_ref()], bump = extra_reward_account.bump)]
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
     pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
-    #[account(mut, seeds = [pool.load()?.mint.key().as_ref()], bump = pool.load()?.bump)]
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
     pub pool: Loader<'info, FarmPoolAccount>,
-    pub authority: Signer<'info>,
-    #[account(constraint = mint.key() == pool.load()?.mint)]
-    pub mint: Box<Account<'info, Mint>>,
+    pub keeper: Signer<'info>,
+    #[account(mut, constraint = keeper_vault.owner == keeper.key())]
+    pub keeper_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = reward_vault.owner == state.key())]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
     #[account(mut, constraint = pool_vault.owner == pool.key())]
     pub pool_vault: Box<Account<'info, TokenAccount>>,
-    #[account(mut, constraint = user_vault.owner == authority.key())]
-    pub user_vault: Box<Account<'info, TokenAccount>>,
-    pub system_program: Program<'info, System>,
     #[account(constraint = token_program.key == &token::ID)]
     pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct Harvest<'info> {
-    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump = user.load()?.bump, has_one = pool, has_one = authority)]
+pub struct CrankHarvest<'info> {
+    #[account(mut, seeds = [pool.key().as_ref(), user.load()?.authority.as_ref()], bump = user.load()?.bump, has_one = pool)]
     pub user: Loader<'info, FarmPoolUserAccount>,
-    #[account(mut, seeds = [b"state".as_ref()], bump = state.load()?.bump)]
+    #[account(mut, seeds = [user.key().as_ref(), position.load()?.index.to_le_bytes().as_ref()], bump = position.load()?.bump, has_one = user)]
+    pub position: Loader<'info, FarmPoolPositionAccount>,
+    #[account(mut, seeds = [b"state".as_ref(), state.load()?.get_state_id_seed().as_ref()], bump = state.load()?.bump)]
     pub state: Loader<'info, StateAccount>,
-    #[account(seeds = [b"extra".as_ref()], bump = extra_reward_account.bump)]
+    #[account(constraint = extra_reward_account.is_for_pool(&extra_reward_account.key(), &pool.load()?))]
     pub extra_reward_account: Box<Account<'info, ExtraRewardsAccount>>,
-    #[account(mut, seeds = [pool.load()?.mint.key().as_ref()], bump = pool.load()?.bump)]
+    #[account(mut, seeds = [pool.load()?.mint.key().as_ref(), pool.load()?.get_pool_id_seed().as_ref(), pool.load()?.get_state_seed().as_ref()], bump = pool.load()?.bump, has_one = state)]
     pub pool: Loader<'info, FarmPoolAccount>,
-    pub authority: Signer<'info>,
-    #[account(constraint = mint.key() == pool.load()?.mint)]
-    pub mint: Box<Account<'info, Mint>>,
+    pub keeper: Signer<'info>,
+    #[account(mut, constraint = keeper_vault.owner == keeper.key())]
+    pub keeper_vault: Box<Account<'info, TokenAccount>>,
     #[account(mut, constraint = reward_vault.owner == state.key())]
     pub reward_vault: Box<Account<'info, TokenAccount>>,
-    #[account(mut, constraint = user_vault.owner == authority.key())]
+    #[account(mut, constraint = user_vault.owner == user.load()?.authority)]
     pub user_vault: Box<Account<'info, TokenAccount>>,
-    pub system_program: Program<'info, System>,
     #[account(constraint = token_program.key == &token::ID)]
     pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
@@ -571,9 +2773,338 @@ pub struct StateAccount {
     pub reward_mint: Pubkey,
     pub reward_vault: Pubkey,
     pub bump: u8,
-    pub total_point: u64,
+    pub total_point: u64, // sum of the POOL_RATE_GLOBAL pool points
     pub start_time: i64,
     pub token_per_second: u64,
+    pub total_funded: u64,
+    pub total_distributed: u64,
+    pub reward_committed: u64, // accrued to users but not harvested yet
+    pub end_time: i64, // 0 => emission never ends
+    pub halving_interval: i64, // 0 => no halving
+    pub halving_start: i64,
+    pub schedule_len: u64,
+    pub schedule_start_times: [i64; 10], // MAX_EMISSION_STEPS
+    pub schedule_rates: [u64; 10],
+    pub total_pool_token_per_second: u64, // sum of POOL_RATE_FIXED pool rates
+    pub state_id: u64, // 0 => legacy seeds [b"state"]
+    pub pending_authority: Pubkey, // set by propose_state_authority until accepted
+    // roles next to the authority, default when unset
+    pub funder: Pubkey, // fund_reward_token
+    pub operator: Pubkey, // rates, schedules, points, multipliers & tier configs
+    pub pauser: Pubkey, // pause only
+    pub paused: u8, // blocks stake & harvest in every pool
+    pub unstake_paused: u8, // blocks unstake in every pool
+    pub timelock_delay: i64, // 0 => admin changes apply directly
+    // caps on admin changes, 0 => no cap
+    pub max_token_per_second: u64,
+    pub max_extra_percentage: u64, // capped at FULL_100 either way
+    pub max_rate_change_per_day: u64, // token_per_second change, pro rata over a day
+    pub max_lock_duration: i64,
+    pub last_rate_change_time: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy)]
+pub struct EmissionStep {
+    start_time: i64,
+    token_per_second: u64,
+}
+
+impl StateAccount {
+    fn is_funder(&self, key: &Pubkey) -> bool {
+        *key == self.authority || *key == self.funder
+    }
+    fn is_operator(&self, key: &Pubkey) -> bool {
+        *key == self.authority || *key == self.operator
+    }
+    fn is_pauser(&self, key: &Pubkey) -> bool {
+        *key == self.authority || *key == self.pauser
+    }
+    fn assert_token_per_second(&self, token_per_second: u64) -> Result<()> {
+        if self.max_token_per_second > 0 {
+            require!(token_per_second <= self.max_token_per_second, ErrorCode::RateTooHigh);
+        }
+        Ok(())
+    }
+    // `elapsed` seconds after a rate of `from`, pro rata over at most a day
+    fn assert_rate_change(&self, from: u64, to: u64, elapsed: i64) -> Result<()> {
+        self.assert_token_per_second(to)?;
+        if self.max_rate_change_per_day > 0 {
+            let elapsed = elapsed.max(0).min(SECONDS_PER_DAY);
+            let max_change = u128::from(self.max_rate_change_per_day)
+                .checked_mul(u128::try_from(elapsed).unwrap())
+                .unwrap()
+                .checked_div(u128::try_from(SECONDS_PER_DAY).unwrap())
+                .unwrap();
+            let change = if to > from { to - from } else { from - to };
+            require!(u128::from(change) <= max_change, ErrorCode::RateChangeTooHigh);
+        }
+        Ok(())
+    }
+    fn set_token_per_second(&mut self, token_per_second: u64, now: i64) -> Result<()> {
+        let elapsed = now.checked_sub(self.last_rate_change_time).unwrap();
+        self.assert_rate_change(self.token_per_second, token_per_second, elapsed)?;
+        self.token_per_second = token_per_second;
+        self.last_rate_change_time = now;
+        Ok(())
+    }
+    fn validate_extra_reward_configs(&self, configs: &[DurationExtraRewardConfig]) -> Result<()> {
+        let mut max_extra_percentage = FULL_100;
+        if self.max_extra_percentage > 0 {
+            max_extra_percentage = self.max_extra_percentage;
+        }
+        for config in configs.iter() {
+            require!(
+                config.extra_percentage <= max_extra_percentage,
+                ErrorCode::ExtraPercentageTooHigh
+            );
+            if self.max_lock_duration > 0 {
+                require!(
+                    config.duration <= self.max_lock_duration,
+                    ErrorCode::LockDurationTooLong
+                );
+            }
+        }
+        Ok(())
+    }
+    // the same roles as the direct instructions, the delay is up to the authority
+    fn assert_can_queue(&self, kind: u8, key: &Pubkey) -> Result<()> {
+        require!(kind <= CHANGE_POOL_RATE_MODE, ErrorCode::InvalidQueuedChange);
+        if kind == CHANGE_TIMELOCK_DELAY {
+            require!(*key == self.authority, ErrorCode::Unauthorized);
+        }
+        require!(self.is_operator(key), ErrorCode::Unauthorized);
+        Ok(())
+    }
+    fn get_state_id_seed(&self) -> Vec<u8> {
+        get_id_seed(self.state_id)
+    }
+    // funded rewards nobody has a claim on yet
+    fn get_available_reward(&self) -> u64 {
+        self.total_funded
+            .saturating_sub(self.total_distributed)
+            .saturating_sub(self.reward_committed)
+    }
+    // seconds until emission stops at the current rate
+    fn get_runway(&self, now: i64) -> u64 {
+        let token_per_second = self
+            .get_token_per_second(now)
+            .checked_add(self.total_pool_token_per_second)
+            .unwrap();
+        if token_per_second == 0 {
+            return u64::MAX;
+        }
+        self.get_available_reward() / token_per_second
+    }
+    fn set_emission_schedule(
+        &mut self,
+        end_time: i64,
+        steps: &[EmissionStep],
+        halving_interval: i64,
+        now: i64,
+    ) -> Result<()> {
+        require!(end_time >= 0, ErrorCode::InvalidSchedule);
+        require!(halving_interval >= 0, ErrorCode::InvalidSchedule);
+        require!(steps.len() <= MAX_EMISSION_STEPS, ErrorCode::InvalidSchedule);
+        // steps and halving are alternative schedules
+        require!(halving_interval == 0 || steps.is_empty(), ErrorCode::InvalidSchedule);
+        let mut start_time = 0;
+        for step in steps.iter() {
+            require!(step.start_time > start_time, ErrorCode::InvalidSchedule);
+            start_time = step.start_time;
+        }
+        let current_token_per_second = self.get_token_per_second(now);
+
+        self.end_time = end_time;
+        self.halving_interval = halving_interval;
+        self.halving_start = now;
+        let mut schedule_start_times = [0; MAX_EMISSION_STEPS];
+        let mut schedule_rates = [0; MAX_EMISSION_STEPS];
+        for (i, step) in steps.iter().enumerate() {
+            schedule_start_times[i] = step.start_time;
+            schedule_rates[i] = step.token_per_second;
+        }
+        self.schedule_len = steps.len() as u64;
+        self.schedule_start_times = schedule_start_times;
+        self.schedule_rates = schedule_rates;
+
+        // every rate change of the new schedule (steps, halvings & the end) is a change
+        // from the rate before it, the first from the current rate
+        let mut rate_time = self.last_rate_change_time;
+        let mut token_per_second = current_token_per_second;
+        let mut time = now;
+        while time < i64::MAX {
+            let next_token_per_second = self.get_token_per_second(time);
+            if next_token_per_second != token_per_second {
+                self.assert_rate_change(
+                    token_per_second,
+                    next_token_per_second,
+                    time.checked_sub(rate_time).unwrap(),
+                )?;
+                rate_time = time;
+                token_per_second = next_token_per_second;
+            }
+            time = self.get_next_rate_change(time);
+        }
+        if self.get_token_per_second(now) != current_token_per_second {
+            self.last_rate_change_time = now;
+        }
+        Ok(())
+    }
+    fn get_token_per_second(&self, time: i64) -> u64 {
+        if self.end_time > 0 && time >= self.end_time {
+            return 0;
+        }
+        let mut token_per_second = self.token_per_second;
+        let schedule_start_times = self.schedule_start_times;
+        let schedule_rates = self.schedule_rates;
+        for i in 0..self.schedule_len as usize {
+            if schedule_start_times[i] <= time {
+                token_per_second = schedule_rates[i];
+            }
+        }
+        if self.halving_interval > 0 && time >= self.halving_start {
+            let halvings = (time - self.halving_start) / self.halving_interval;
+            if halvings >= MAX_HALVINGS {
+                return 0;
+            }
+            token_per_second = token_per_second >> halvings;
+        }
+        token_per_second
+    }
+    // next time after `time` where the rate can change
+    fn get_next_rate_change(&self, time: i64) -> i64 {
+        let mut next = i64::MAX;
+        if self.end_time > time {
+            next = next.min(self.end_time);
+        }
+        let schedule_start_times = self.schedule_start_times;
+        for i in 0..self.schedule_len as usize {
+            if schedule_start_times[i] > time {
+                next = next.min(schedule_start_times[i]);
+            }
+        }
+        if self.halving_interval > 0 {
+            if time < self.halving_start {
+                next = next.min(self.halving_start);
+            } else {
+                let halvings = (time - self.halving_start) / self.halving_interval;
+                if halvings < MAX_HALVINGS {
+                    next = next.min(
+                        self.halving_start
+                            .checked_add((halvings + 1).checked_mul(self.halving_interval).unwrap())
+                            .unwrap(),
+                    );
+                }
+            }
+        }
+        next
+    }
+    // seconds between two timestamps before the campaign end
+    fn get_active_seconds(&self, from: i64, to: i64) -> i64 {
+        let mut to = to;
+        if self.end_time > 0 {
+            to = to.min(self.end_time);
+        }
+        to.checked_sub(from).unwrap().max(0)
+    }
+    // total emission between two timestamps following the schedule
+    fn get_emission(&self, from: i64, to: i64) -> u128 {
+        let mut emission: u128 = 0;
+        let mut time = from;
+        while time < to {
+            let next = self.get_next_rate_change(time).min(to);
+            emission = emission
+                .checked_add(
+                    u128::from(self.get_token_per_second(time))
+                        .checked_mul(u128::try_from(next - time).unwrap())
+                        .unwrap(),
+                )
+                .unwrap();
+            time = next;
+        }
+        emission
+    }
+    fn commit_reward(&mut self, amount: u64) {
+        self.reward_committed = self.reward_committed.checked_add(amount).unwrap();
+    }
+    fn release_reward(&mut self, amount: u64) {
+        self.reward_committed = self.reward_committed.saturating_sub(amount);
+    }
+    fn distribute_reward(&mut self, amount: u64) {
+        self.release_reward(amount);
+        self.total_distributed = self.total_distributed.checked_add(amount).unwrap();
+    }
+}
+
+#[account]
+pub struct PoolRegistryAccount {
+    pub bump: u8,
+    pub pools: Vec<Pubkey>,
+} // 1 + 4 + 32 * 32
+
+impl PoolRegistryAccount {
+    fn add_pool(&mut self, pool: Pubkey) -> Result<()> {
+        require!(!self.pools.contains(&pool), ErrorCode::PoolAlreadyRegistered);
+        require!(self.pools.len() < MAX_POOLS, ErrorCode::TooManyPools);
+        self.pools.push(pool);
+        Ok(())
+    }
+    // the remaining accounts must be exactly the registered pools, in any order
+    fn validate_pools(&self, accounts: &[AccountInfo]) -> Result<()> {
+        require!(accounts.len() == self.pools.len(), ErrorCode::MissingPools);
+        for pool in self.pools.iter() {
+            require!(
+                accounts.iter().any(|info| info.key == pool),
+                ErrorCode::MissingPools
+            );
+        }
+        Ok(())
+    }
+}
+
+#[account]
+pub struct AdminQueueAccount {
+    pub bump: u8,
+    pub state: Pubkey,
+    pub next_id: u64,
+    pub changes: Vec<QueuedChange>,
+} // 1 + 32 + 8 + 4 + 402 * 8
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct QueuedChange {
+    id: u64,
+    kind: u8, // CHANGE_*
+    target: Pubkey, // pool or extra reward account, default for state changes
+    value: u64,
+    configs: Vec<DurationExtraRewardConfig>, // CHANGE_EXTRA_REWARD_CONFIGS only
+    eta: i64,
+    rate_mode: u8, // CHANGE_POOL_RATE_MODE only, value is the fixed token_per_second
+    end_time: i64, // CHANGE_EMISSION_SCHEDULE only
+    steps: Vec<EmissionStep>,
+    halving_interval: i64,
+} // 8 + 1 + 32 + 8 + 4 + 16 * 10 + 8 + 1 + 8 + 4 + 16 * 10 + 8
+
+impl AdminQueueAccount {
+    fn push(&mut self, mut change: QueuedChange) -> Result<u64> {
+        require!(self.changes.len() < MAX_QUEUED_CHANGES, ErrorCode::TooManyQueuedChanges);
+        change.id = self.next_id;
+        self.next_id = self.next_id.checked_add(1).unwrap();
+        self.changes.push(change);
+        Ok(self.next_id - 1)
+    }
+    fn remove(&mut self, id: u64) -> Result<QueuedChange> {
+        let index = self
+            .changes
+            .iter()
+            .position(|change| change.id == id)
+            .ok_or(ErrorCode::QueuedChangeNotFound)?;
+        Ok(self.changes.remove(index))
+    }
+    fn take_due(&mut self, id: u64, now: i64) -> Result<QueuedChange> {
+        let change = self.remove(id)?;
+        require!(change.eta <= now, ErrorCode::TimelockNotReady);
+        Ok(change)
+    }
 }
 
 #[account]
@@ -581,7 +3112,12 @@ pub struct ExtraRewardsAccount {
     pub bump: u8,
     pub authority: Pubkey,
     pub configs: Vec<DurationExtraRewardConfig>,
-} // 37 + 10 * 16
+    pub penalty: EarlyUnstakePenaltyConfig,
+    pub pool: Pubkey, // default for the global configs
+    pub version: u64, // bumped on every change, positions keep the version they locked under
+    pub state: Pubkey,
+    pub pending_authority: Pubkey,
+} // 37 + 10 * 16 + 51 + 32 + 8 + 32 + 32
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy)]
 pub struct DurationExtraRewardConfig {
@@ -589,7 +3125,31 @@ pub struct DurationExtraRewardConfig {
     extra_percentage: u64, // decimals 9, MAX = 100_000_000_000
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy)]
+pub struct EarlyUnstakePenaltyConfig {
+    enabled: bool,
+    principal_percentage: u64, // decimals 9, MAX = 100_000_000_000
+    extra_reward_percentage: u64, // share of accrued extra_reward forfeited
+    linear_decay: bool, // decays to zero at lock end
+    destination: u8, // PENALTY_TO_TREASURY | PENALTY_REDISTRIBUTE | PENALTY_BURN
+    treasury: Pubkey,
+}
+
+impl EarlyUnstakePenaltyConfig {
+    fn validate(&self) -> Result<()> {
+        require!(self.principal_percentage <= FULL_100, ErrorCode::InvalidPenalty);
+        require!(self.extra_reward_percentage <= FULL_100, ErrorCode::InvalidPenalty);
+        require!(self.destination <= PENALTY_BURN, ErrorCode::InvalidPenalty);
+        Ok(())
+    }
+}
+
 impl ExtraRewardsAccount {
+    fn set_configs(&mut self, configs: Vec<DurationExtraRewardConfig>) -> Result<()> {
+        self.configs = configs;
+        self.version = self.version.checked_add(1).unwrap();
+        self.validate()
+    }
     fn validate(&mut self) -> Result<()> {
         if self.configs.len() > 1 {
             let mut duration = 0;
@@ -604,8 +3164,16 @@ impl ExtraRewardsAccount {
                 extra_percentage = config.extra_percentage;
             }
         }
+        self.penalty.validate()?;
         Ok(())
     }
+    // a pool with its own configs can't fall back to the global ones
+    fn is_for_pool(&self, key: &Pubkey, pool: &FarmPoolAccount) -> bool {
+        if pool.extra_reward_account == Pubkey::default() {
+            return self.pool == Pubkey::default() && self.state == pool.state;
+        }
+        pool.extra_reward_account == *key
+    }
     fn validate_lock_duration(&mut self, lock_duration: &i64) -> Result<()> {
         for config in self.configs.iter() {
             if config.duration == *lock_duration {
@@ -614,7 +3182,7 @@ impl ExtraRewardsAccount {
         }
         Err(ErrorCode::InvalidLockDuration.into())
     }
-    fn get_extra_reward_percentage(&mut self, lock_duration: &i64) -> u64 {
+    fn get_extra_reward_percentage(&self, lock_duration: &i64) -> u64 {
         let reversed_configs: Vec<DurationExtraRewardConfig> =
             self.configs.iter().rev().cloned().collect();
         for tier in reversed_configs.iter() {
@@ -636,12 +3204,519 @@ pub struct FarmPoolAccount {
     pub point: u64,
     pub last_reward_time: i64,
     pub acc_reward_per_share: u128,
-    pub amount_multipler: u64,
+    pub amount_multipler: u64, // unused, kept for the layout
     pub total_user: u64,
+    pub acc_penalty_per_share: u128, // redistributed early unstake penalties
+    pub extra_reward_account: Pubkey, // default when the global configs apply
+    pub unbonding_period: i64, // 0 => unstake directly
+    pub rate_mode: u8, // POOL_RATE_GLOBAL | POOL_RATE_FIXED
+    pub token_per_second: u64, // POOL_RATE_FIXED only
+    pub stream_count: u8,
+    pub pool_id: u64, // 0 => legacy seeds [mint]
+    pub state: Pubkey,
+    pub state_id: u64,
+    pub pending_authority: Pubkey,
+    pub paused: u8, // blocks stake & harvest
+    pub unstake_paused: u8, // blocks unstake
+    pub emergency_unlocked: u8, // 1 => emergency_withdraw skips the lock & unbonding end
+    pub keeper_tip_percentage: u64, // share of cranked rewards paid to the keeper
+    pub vesting_mode: u8, // VESTING_NONE | VESTING_ALL | VESTING_EXTRA
+    pub vesting_period: i64,
+    pub vesting_early_claim_penalty: u64, // share of the unvested rest forfeited by an early claim
+}
+
+// an empty seed keeps id 0 on the legacy address, e.g. pool 0 on [mint]
+fn get_id_seed(id: u64) -> Vec<u8> {
+    if id == 0 {
+        return Vec::new();
+    }
+    id.to_le_bytes().to_vec()
+}
+
+// pools of the first state keep [mint, pool_id], other states add their key
+fn get_state_seed(state: &Pubkey, state_id: u64) -> Vec<u8> {
+    if state_id == 0 {
+        return Vec::new();
+    }
+    state.to_bytes().to_vec()
+}
+
+// pays the position rewards and the streams whose (stream vault, token account) pairs
+// follow the streams in the remaining accounts, stream token accounts must belong to
+// stream_owner when it is set
+#[allow(clippy::too_many_arguments)]
+fn harvest_position<'info>(
+    state_loader: &Loader<'info, StateAccount>,
+    pool_loader: &Loader<'info, FarmPoolAccount>,
+    user_loader: &Loader<'info, FarmPoolUserAccount>,
+    position_loader: &Loader<'info, FarmPoolPositionAccount>,
+    extra_account: &ExtraRewardsAccount,
+    reward_vault: &Account<'info, TokenAccount>,
+    user_vault: &Account<'info, TokenAccount>,
+    keeper_vault: Option<&Account<'info, TokenAccount>>, // cranks tip the keeper
+    token_program: &Program<'info, Token>,
+    clock: &Sysvar<'info, Clock>,
+    program_id: &Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+    authority: &Pubkey,
+    stream_owner: Option<Pubkey>,
+    vesting_loader: Option<&Loader<'info, VestingAccount>>,
+) -> ProgramResult {
+    let mut state = state_loader.load_mut()?;
+    let mut pool = pool_loader.load_mut()?;
+    let mut user = user_loader.load_mut()?;
+    let mut position = position_loader.load_mut()?;
+    let pool_key = pool_loader.key();
+
+    pool.assert_active(&state)?;
+    pool.update(&mut state, clock)?;
+    let streams = pool.update_streams(&pool_key, program_id, remaining_accounts, clock.unix_timestamp)?;
+    let extra_percentage = position.get_extra_percentage(extra_account, clock.unix_timestamp);
+    position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+    position.calculate_stream_rewards(&streams)?;
+    let penalty_share = position.claim_penalty_share(&pool)?;
+    user.amount = user.amount.checked_add(penalty_share).unwrap();
+    pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+
+    let mut total_reward: u64 = position.reward_amount.checked_add(position.extra_reward).unwrap().try_into().unwrap();
+    if pool.vesting_mode != VESTING_NONE {
+        let vesting_loader = vesting_loader.ok_or(ErrorCode::VestingRequired)?;
+        let mut vested = total_reward;
+        if pool.vesting_mode == VESTING_EXTRA {
+            vested = position.extra_reward.try_into().unwrap();
+        }
+        // vested rewards stay committed in the reward vault until claimed
+        let mut vesting = vesting_loader.load_mut()?;
+        vesting.deposit(vested, clock.unix_timestamp, pool.vesting_period);
+        total_reward = total_reward.checked_sub(vested).unwrap();
+        emit!(RewardVested {
+            pool: pool_key,
+            position: position_loader.key(),
+            vesting: vesting_loader.key(),
+            amount: vested,
+            end_time: vesting.end_time
+        });
+    }
+    let mut keeper_tip = 0;
+    if keeper_vault.is_some() {
+        keeper_tip = pool.get_keeper_tip(total_reward);
+    }
+    let amount = total_reward.checked_sub(keeper_tip).unwrap();
+    state.distribute_reward(total_reward);
+    let state_bump = state.bump;
+    let state_id_seed = state.get_state_id_seed();
+    drop(state);
+
+    let cpi_accounts = Transfer {
+        from: reward_vault.to_account_info(),
+        to: user_vault.to_account_info(),
+        authority: state_loader.to_account_info(),
+    };
+
+    let seeds = &[b"state".as_ref(), state_id_seed.as_ref(), &[state_bump]];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)?;
+    if let Some(keeper_vault) = keeper_vault {
+        if keeper_tip > 0 {
+            let cpi_accounts = Transfer {
+                from: reward_vault.to_account_info(),
+                to: keeper_vault.to_account_info(),
+                authority: state_loader.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+            token::transfer(cpi_ctx, keeper_tip)?;
+        }
+        emit!(KeeperTipPaid {
+            pool: pool_key,
+            position: position_loader.key(),
+            keeper: *authority,
+            amount: keeper_tip
+        });
+    }
+
+    position.reward_amount = 0;
+    position.extra_reward = 0;
+    position.calculate_reward_debt(&pool)?;
+    position.calculate_stream_reward_debts(&streams)?;
+    emit!(UserHarvested {
+        pool: pool_key,
+        user: user_loader.key(),
+        position: position_loader.key(),
+        authority: *authority,
+        amount
+    });
+
+    let payouts = &remaining_accounts[streams.len()..];
+    require!(payouts.len() % 2 == 0, ErrorCode::InvalidStream);
+    let mut stream_amounts = position.stream_reward_amount;
+    for pair in payouts.chunks(2) {
+        let (stream_vault, user_stream_vault) = (&pair[0], &pair[1]);
+        let i = streams
+            .iter()
+            .position(|s| s.load().map(|s| s.vault == stream_vault.key()).unwrap_or(false))
+            .ok_or(ErrorCode::InvalidStream)?;
+        let mut stream = streams[i].load_mut()?;
+        let destination = Account::<TokenAccount>::try_from(user_stream_vault)?;
+        if let Some(owner) = stream_owner {
+            require!(destination.owner == owner, ErrorCode::InvalidStream);
+        }
+        require!(destination.mint == stream.mint, ErrorCode::InvalidStream);
+
+        let amount: u64 = stream_amounts[i].try_into().unwrap();
+        stream_amounts[i] = 0;
+        stream.distribute_reward(amount);
+        let stream_bump = stream.bump;
+        let index_bytes = stream.index.to_le_bytes();
+        drop(stream);
+
+        let cpi_accounts = Transfer {
+            from: stream_vault.clone(),
+            to: user_stream_vault.clone(),
+            authority: streams[i].to_account_info(),
+        };
+        let seeds = &[b"stream".as_ref(), pool_key.as_ref(), index_bytes.as_ref(), &[stream_bump]];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        emit!(UserStreamHarvested {
+            pool: pool_key,
+            stream: streams[i].key(),
+            position: position_loader.key(),
+            authority: *authority,
+            amount
+        });
+    }
+    position.stream_reward_amount = stream_amounts;
+    Ok(())
+}
+
+// moves the position rewards from the reward vault into the pool vault as stake,
+// the lock and its end stay as they are
+#[allow(clippy::too_many_arguments)]
+fn compound_position<'info>(
+    state_loader: &Loader<'info, StateAccount>,
+    pool_loader: &Loader<'info, FarmPoolAccount>,
+    user_loader: &Loader<'info, FarmPoolUserAccount>,
+    position_loader: &Loader<'info, FarmPoolPositionAccount>,
+    extra_account: &ExtraRewardsAccount,
+    reward_vault: &Account<'info, TokenAccount>,
+    pool_vault: &Account<'info, TokenAccount>,
+    keeper_vault: Option<&Account<'info, TokenAccount>>, // cranks tip the keeper
+    token_program: &Program<'info, Token>,
+    clock: &Sysvar<'info, Clock>,
+    program_id: &Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<(u64, u64)> {
+    let mut state = state_loader.load_mut()?;
+    let mut pool = pool_loader.load_mut()?;
+    let mut user = user_loader.load_mut()?;
+    let mut position = position_loader.load_mut()?;
+    let now = clock.unix_timestamp;
+
+    pool.assert_active(&state)?;
+    // compounding would skip vesting
+    require!(
+        pool.mint == state.reward_mint && pool.vesting_mode == VESTING_NONE,
+        ErrorCode::InvalidCompoundPool
+    );
+    pool.update(&mut state, clock)?;
+    let streams = pool.update_streams(&pool_loader.key(), program_id, remaining_accounts, now)?;
+    let extra_percentage = position.get_extra_percentage(extra_account, now);
+    position.calculate_reward_amount(&pool, &extra_percentage, &mut state)?;
+    position.calculate_stream_rewards(&streams)?;
+    let penalty_share = position.claim_penalty_share(&pool)?;
+    user.amount = user.amount.checked_add(penalty_share).unwrap();
+    pool.amount = pool.amount.checked_add(penalty_share).unwrap();
+
+    let total_reward: u64 = position.reward_amount.checked_add(position.extra_reward).unwrap().try_into().unwrap();
+    let mut keeper_tip = 0;
+    if keeper_vault.is_some() {
+        keeper_tip = pool.get_keeper_tip(total_reward);
+    }
+    let amount = total_reward.checked_sub(keeper_tip).unwrap();
+    state.distribute_reward(total_reward);
+    position.reward_amount = 0;
+    position.extra_reward = 0;
+    position.amount = position.amount.checked_add(amount).unwrap();
+    user.amount = user.amount.checked_add(amount).unwrap();
+    pool.amount = pool.amount.checked_add(amount).unwrap();
+    position.calculate_reward_debt(&pool)?;
+    position.calculate_stream_reward_debts(&streams)?;
+    let state_bump = state.bump;
+    let state_id_seed = state.get_state_id_seed();
+    drop(state);
+
+    let cpi_accounts = Transfer {
+        from: reward_vault.to_account_info(),
+        to: pool_vault.to_account_info(),
+        authority: state_loader.to_account_info(),
+    };
+    let seeds = &[b"state".as_ref(), state_id_seed.as_ref(), &[state_bump]];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)?;
+    if let Some(keeper_vault) = keeper_vault {
+        if keeper_tip > 0 {
+            let cpi_accounts = Transfer {
+                from: reward_vault.to_account_info(),
+                to: keeper_vault.to_account_info(),
+                authority: state_loader.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+            token::transfer(cpi_ctx, keeper_tip)?;
+        }
+    }
+    Ok((amount, keeper_tip))
+}
+
+// settles every pool passed in the remaining accounts before a global change
+fn update_pools<'info>(
+    state: &mut StateAccount,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'info>],
+    clock: &Sysvar<'info, Clock>,
+) -> Result<()> {
+    for info in accounts.iter() {
+        let loader = Loader::<FarmPoolAccount>::try_from(program_id, info)?;
+        loader.load_mut()?.update(state, clock)?;
+    }
+    Ok(())
+}
+
+// the queued forms share the role & delay checks
+fn queue_admin_change(accounts: &mut QueueChange, change: QueuedChange) -> Result<()> {
+    let state = accounts.state.load()?;
+    state.assert_can_queue(change.kind, &accounts.authority.key())?;
+    let earliest = accounts
+        .clock
+        .unix_timestamp
+        .checked_add(state.timelock_delay)
+        .ok_or(ErrorCode::TimelockTooLong)?;
+    require!(change.eta >= earliest, ErrorCode::TimelockTooShort);
+    let (kind, target, value, eta) = (change.kind, change.target, change.value, change.eta);
+    let id = accounts.queue.push(change)?;
+    emit!(ChangeQueued {
+        state: accounts.state.key(),
+        id,
+        kind,
+        target,
+        value,
+        eta
+    });
+    Ok(())
+}
+
+fn set_pool_rate_mode(
+    state: &mut StateAccount,
+    pool: &mut FarmPoolAccount,
+    registry: &PoolRegistryAccount,
+    accounts: &[AccountInfo],
+    rate_mode: u8,
+    token_per_second: u64,
+) -> Result<()> {
+    require!(rate_mode <= POOL_RATE_FIXED, ErrorCode::InvalidRateMode);
+    let share_point = pool.share_point();
+    state.total_point = state
+        .total_point
+        .checked_sub(share_point)
+        .unwrap();
+    state.total_pool_token_per_second = state
+        .total_pool_token_per_second
+        .checked_sub(pool.get_fixed_token_per_second())
+        .unwrap();
+    if rate_mode == POOL_RATE_FIXED {
+        state.assert_token_per_second(token_per_second)?;
+    }
+    pool.rate_mode = rate_mode;
+    pool.token_per_second = token_per_second;
+    if pool.share_point() != share_point {
+        registry.validate_pools(accounts)?;
+    }
+    state.total_point = state
+        .total_point
+        .checked_add(pool.share_point())
+        .unwrap();
+    state.total_pool_token_per_second = state
+        .total_pool_token_per_second
+        .checked_add(pool.get_fixed_token_per_second())
+        .unwrap();
+    Ok(())
+}
+
+fn change_pool_weight(
+    state: &mut StateAccount,
+    pool: &mut FarmPoolAccount,
+    registry: &PoolRegistryAccount,
+    accounts: &[AccountInfo],
+    point: u64,
+) -> Result<()> {
+    let share_point = pool.share_point();
+    state.total_point = state.total_point.checked_sub(share_point).unwrap();
+    pool.point = point;
+    // the other pools only need settling when the share changes
+    if pool.share_point() != share_point {
+        registry.validate_pools(accounts)?;
+    }
+    state.total_point = state.total_point.checked_add(pool.share_point()).unwrap();
+    Ok(())
+}
+
+// keeps the data of a first version account & closes it, so that finish_migrate_*
+// can create it again at the same address
+fn begin_migration(
+    accounts: &mut BeginMigration,
+    program_id: &Pubkey,
+    bump: u8,
+    legacy_size: usize,
+    discriminator: [u8; 8],
+    authority_offset: usize,
+) -> ProgramResult {
+    let legacy = &accounts.legacy;
+    require!(legacy.owner == program_id, ErrorCode::NothingToMigrate);
+    {
+        let mut data = legacy.try_borrow_mut_data()?;
+        require!(
+            data.len() == legacy_size && data[..8] == discriminator,
+            ErrorCode::NothingToMigrate
+        );
+        let authority = Pubkey::new(&data[authority_offset..authority_offset + 32]);
+        require!(authority == accounts.authority.key(), ErrorCode::Unauthorized);
+        let migration = &mut accounts.migration;
+        migration.bump = bump;
+        migration.authority = authority;
+        migration.account = legacy.key();
+        migration.data = data[8..].to_vec();
+        for byte in data.iter_mut() {
+            *byte = 0;
+        }
+    }
+    let authority = accounts.authority.to_account_info();
+    let lamports = authority.lamports().checked_add(legacy.lamports()).unwrap();
+    **legacy.try_borrow_mut_lamports()? = 0;
+    **authority.try_borrow_mut_lamports()? = lamports;
+    Ok(())
+}
+
+#[cfg(feature = "legacy-fixtures")]
+fn write_legacy_fixture_account(
+    accounts: &WriteLegacyFixture,
+    program_id: &Pubkey,
+    seeds: Vec<Vec<u8>>,
+    bump: u8,
+    data: Vec<u8>,
+) -> ProgramResult {
+    let bump_seed = [bump];
+    let mut signer_seeds: Vec<&[u8]> = seeds.iter().map(|seed| seed.as_slice()).collect();
+    signer_seeds.push(&bump_seed);
+    anchor_lang::solana_program::program::invoke_signed(
+        &anchor_lang::solana_program::system_instruction::create_account(
+            accounts.payer.key,
+            accounts.fixture.key,
+            accounts.rent.minimum_balance(data.len()),
+            data.len() as u64,
+            program_id,
+        ),
+        &[
+            accounts.payer.to_account_info(),
+            accounts.fixture.clone(),
+            accounts.system_program.to_account_info(),
+        ],
+        &[&signer_seeds],
+    )?;
+    accounts.fixture.try_borrow_mut_data()?.copy_from_slice(&data);
+    Ok(())
+}
+
+#[cfg(not(feature = "legacy-fixtures"))]
+fn write_legacy_fixture_account(
+    _accounts: &WriteLegacyFixture,
+    _program_id: &Pubkey,
+    _seeds: Vec<Vec<u8>>,
+    _bump: u8,
+    _data: Vec<u8>,
+) -> ProgramResult {
+    Err(ErrorCode::LegacyFixturesDisabled.into())
 }
 
 impl FarmPoolAccount {
-    fn update<'info>(&mut self, state: &StateAccount, clock: &Sysvar<'info, Clock>) -> Result<()> {
+    fn get_pool_id_seed(&self) -> Vec<u8> {
+        get_id_seed(self.pool_id)
+    }
+    fn get_state_seed(&self) -> Vec<u8> {
+        get_state_seed(&self.state, self.state_id)
+    }
+    // the pool share of emission is share_point / state.total_point,
+    // point alone weighs the pool stake against the other pools
+    fn share_point(&self) -> u64 {
+        if self.rate_mode == POOL_RATE_FIXED {
+            return 0;
+        }
+        self.point
+    }
+    // every stream of the pool must lead the remaining accounts, in index order
+    fn load_streams<'info>(
+        &self,
+        pool_key: &Pubkey,
+        program_id: &Pubkey,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<Vec<Loader<'info, RewardStreamAccount>>> {
+        let stream_count = usize::from(self.stream_count);
+        require!(accounts.len() >= stream_count, ErrorCode::InvalidStream);
+        let mut streams = Vec::with_capacity(stream_count);
+        for (i, info) in accounts.iter().take(stream_count).enumerate() {
+            let loader = Loader::<RewardStreamAccount>::try_from(program_id, info)?;
+            {
+                let stream = loader.load()?;
+                require!(stream.pool == *pool_key, ErrorCode::InvalidStream);
+                require!(usize::from(stream.index) == i, ErrorCode::InvalidStream);
+            }
+            streams.push(loader);
+        }
+        Ok(streams)
+    }
+    fn update_streams<'info>(
+        &self,
+        pool_key: &Pubkey,
+        program_id: &Pubkey,
+        accounts: &[AccountInfo<'info>],
+        now: i64,
+    ) -> Result<Vec<Loader<'info, RewardStreamAccount>>> {
+        let streams = self.load_streams(pool_key, program_id, accounts)?;
+        for loader in streams.iter() {
+            loader.load_mut()?.update(self.amount, now);
+        }
+        Ok(streams)
+    }
+    fn assert_active(&self, state: &StateAccount) -> Result<()> {
+        require!(state.paused == 0 && self.paused == 0, ErrorCode::Paused);
+        Ok(())
+    }
+    fn assert_unstake_active(&self, state: &StateAccount) -> Result<()> {
+        require!(
+            state.unstake_paused == 0 && self.unstake_paused == 0,
+            ErrorCode::UnstakePaused
+        );
+        Ok(())
+    }
+    fn get_keeper_tip(&self, reward: u64) -> u64 {
+        u128::from(reward)
+            .checked_mul(u128::from(self.keeper_tip_percentage))
+            .unwrap()
+            .checked_div(u128::from(FULL_100))
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+    fn get_fixed_token_per_second(&self) -> u64 {
+        if self.rate_mode == POOL_RATE_FIXED {
+            return self.token_per_second;
+        }
+        0
+    }
+    fn update<'info>(&mut self, state: &mut StateAccount, clock: &Sysvar<'info, Clock>) -> Result<()> {
+        // state ids are unique, so this keeps pools of other states out of mass updates
+        require!(self.state_id == state.state_id, ErrorCode::InvalidState);
         let seconds = u128::try_from(
             clock
                 .unix_timestamp
@@ -650,16 +3725,30 @@ impl FarmPoolAccount {
         )
         .unwrap();
         let mut reward_per_share: u128 = 0;
-        if self.amount > 0 && seconds > 0 && self.point > 0 {
-            reward_per_share = u128::from(state.token_per_second)
-                .checked_mul(seconds)
-                .unwrap()
-                .checked_mul(u128::from(self.point))
-                .unwrap()
+        let share_point = self.share_point();
+        if self.amount > 0 && seconds > 0 {
+            let mut emission: u128 = 0;
+            if self.rate_mode == POOL_RATE_FIXED {
+                emission = u128::from(self.token_per_second)
+                    .checked_mul(u128::try_from(state.get_active_seconds(self.last_reward_time, clock.unix_timestamp)).unwrap())
+                    .unwrap();
+            } else if share_point > 0 {
+                emission = state
+                    .get_emission(self.last_reward_time, clock.unix_timestamp)
+                    .checked_mul(u128::from(share_point))
+                    .unwrap()
+                    .checked_div(u128::from(state.total_point))
+                    .unwrap();
+            }
+            // emission stops once the funded budget is used up
+            let reward: u64 = emission
+                .min(u128::from(state.get_available_reward()))
+                .try_into()
+                .unwrap();
+            state.commit_reward(reward);
+            reward_per_share = u128::from(reward)
                 .checked_mul(ACC_PRECISION)
                 .unwrap()
-                .checked_div(u128::from(state.total_point))
-                .unwrap()
                 .checked_div(u128::from(self.amount))
                 .unwrap();
         }
@@ -669,24 +3758,188 @@ impl FarmPoolAccount {
             .unwrap();
         self.last_reward_time = clock.unix_timestamp;
 
-        Ok(())
+        Ok(())
+    }
+}
+
+#[account(zero_copy)]
+pub struct RewardStreamAccount {
+    pub bump: u8,
+    pub pool: Pubkey,
+    pub index: u8,
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub vault: Pubkey, // owned by the stream
+    pub token_per_second: u64,
+    pub end_time: i64, // 0 => no end
+    pub last_reward_time: i64,
+    pub acc_reward_per_share: u128,
+    pub total_funded: u64,
+    pub total_distributed: u64,
+    pub reward_committed: u64,
+}
+
+impl RewardStreamAccount {
+    fn get_available_reward(&self) -> u64 {
+        self.total_funded
+            .saturating_sub(self.total_distributed)
+            .saturating_sub(self.reward_committed)
+    }
+    fn update(&mut self, pool_amount: u64, now: i64) {
+        let mut to = now;
+        if self.end_time > 0 {
+            to = to.min(self.end_time);
+        }
+        if pool_amount > 0 && to > self.last_reward_time {
+            let reward: u64 = u128::from(self.token_per_second)
+                .checked_mul(u128::try_from(to.checked_sub(self.last_reward_time).unwrap()).unwrap())
+                .unwrap()
+                .min(u128::from(self.get_available_reward()))
+                .try_into()
+                .unwrap();
+            self.reward_committed = self.reward_committed.checked_add(reward).unwrap();
+            self.acc_reward_per_share = self
+                .acc_reward_per_share
+                .checked_add(
+                    u128::from(reward)
+                        .checked_mul(ACC_PRECISION)
+                        .unwrap()
+                        .checked_div(u128::from(pool_amount))
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        if now > self.last_reward_time {
+            self.last_reward_time = now;
+        }
+    }
+    fn release_reward(&mut self, amount: u64) {
+        self.reward_committed = self.reward_committed.saturating_sub(amount);
+    }
+    fn distribute_reward(&mut self, amount: u64) {
+        self.release_reward(amount);
+        self.total_distributed = self.total_distributed.checked_add(amount).unwrap();
+    }
+}
+
+#[account(zero_copy)]
+pub struct FarmPoolUserAccount {
+    pub bump: u8,
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub amount: u64, // sum of all positions
+    // single stake of accounts created before positions, moved into position 0 by migrate_user
+    pub legacy_reward_amount: u128,
+    pub legacy_extra_reward: u128,
+    pub legacy_reward_debt: u128,
+    pub legacy_last_stake_time: i64,
+    pub legacy_lock_duration: i64,
+    // zeroed reserved space in legacy accounts
+    pub position_count: u64,
+    pub delegate: Pubkey, // can harvest, not unstake; default when unset
+    pub auto_compound: u8, // lets anyone crank compound
+    pub auto_harvest: u8, // lets anyone crank harvest to a token account of the authority
+    pub reserved_3: [u8; 6],
+}
+
+impl FarmPoolUserAccount {
+    // staked before positions existed and not migrated yet
+    fn is_legacy(&self) -> bool {
+        self.position_count == 0
+            && (self.amount > 0 || self.legacy_reward_amount > 0 || self.legacy_extra_reward > 0)
     }
 }
 
 #[account(zero_copy)]
-pub struct FarmPoolUserAccount {
+pub struct FarmPoolPositionAccount {
     pub bump: u8,
     pub pool: Pubkey,
+    pub user: Pubkey,
     pub authority: Pubkey,
+    pub index: u64,
     pub amount: u64,
     pub reward_amount: u128,
     pub extra_reward: u128, // extra from lock duration; ex lock 12M => +10%
     pub reward_debt: u128,
     pub last_stake_time: i64,
     pub lock_duration: i64,
-    pub reserved_1: u128,
-    pub reserved_2: u128,
-    pub reserved_3: u128,
+    pub penalty_debt: u128,
+    // tier terms snapshotted when the lock starts
+    pub extra_percentage: u64,
+    pub config_version: u64,
+    pub penalty_enabled: u8,
+    pub penalty_linear_decay: u8,
+    pub penalty_principal_percentage: u64,
+    pub penalty_extra_reward_percentage: u64,
+    pub unbonding_amount: u64, // not part of amount, earns nothing
+    pub unbonding_end: i64,
+    // per reward stream of the pool, by stream index
+    pub stream_reward_debt: [u128; 8],
+    pub stream_reward_amount: [u128; 8],
+    pub funder_locked: u8, // locked by stake_for until the lock ends, no early or emergency exit
+    pub penalty_destination: u8, // snapshotted with the other penalty terms
+    pub last_reward_time: i64, // last settlement, splits the extra percentage at the lock end
+}
+
+#[account(zero_copy)]
+pub struct VestingAccount {
+    pub bump: u8,
+    pub user: Pubkey,
+    pub authority: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64, // not claimed yet
+    pub vested: u64, // part of amount released at start_time
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+impl VestingAccount {
+    // the rest vests linearly from start_time to end_time
+    fn get_claimable(&self, now: i64) -> u64 {
+        if now >= self.end_time {
+            return self.amount;
+        }
+        let vesting = self.amount.checked_sub(self.vested).unwrap();
+        let released: u64 = u128::from(vesting)
+            .checked_mul(u128::try_from(now.checked_sub(self.start_time).unwrap()).unwrap())
+            .unwrap()
+            .checked_div(u128::try_from(self.end_time.checked_sub(self.start_time).unwrap()).unwrap())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        self.vested.checked_add(released).unwrap()
+    }
+    // the end moves to the average of both ends weighted by amount, but never
+    // so far that the balance not released yet would release slower than before
+    fn deposit(&mut self, amount: u64, now: i64, period: i64) {
+        let vested = self.get_claimable(now);
+        let unreleased = u128::from(self.amount.checked_sub(vested).unwrap());
+        let mut duration = period;
+        if unreleased > 0 && self.end_time > now {
+            let remaining = u128::try_from(self.end_time.checked_sub(now).unwrap()).unwrap();
+            let total = unreleased.checked_add(u128::from(amount)).unwrap();
+            let weighted = unreleased
+                .checked_mul(remaining)
+                .unwrap()
+                .checked_add(u128::from(amount).checked_mul(u128::try_from(period).unwrap()).unwrap())
+                .unwrap()
+                .checked_div(total)
+                .unwrap();
+            let max_duration = total.checked_mul(remaining).unwrap().checked_div(unreleased).unwrap();
+            duration = weighted.min(max_duration).try_into().unwrap();
+        }
+        self.vested = vested;
+        self.amount = self.amount.checked_add(amount).unwrap();
+        self.start_time = now;
+        self.end_time = now.checked_add(duration).unwrap();
+    }
+    fn claim(&mut self, claimable: u64, now: i64) {
+        self.amount = self.amount.checked_sub(claimable).unwrap();
+        self.vested = 0;
+        if now < self.end_time {
+            self.start_time = now;
+        }
+    }
 }
 
 #[account(zero_copy)]
@@ -696,11 +3949,82 @@ pub struct FarmUserEtherAddress {
     pub ether_address: [u8; 42],
 }
 
-impl FarmPoolUserAccount {
+impl FarmPoolPositionAccount {
+    // a new tranche restarts the cooldown of the unbonding balance, so a balance that
+    // has cooled down already is taken out first, returns the amount to pay out
+    fn add_unbonding(&mut self, amount: u64, unbonding_period: i64, now: i64) -> u64 {
+        let mut unbonded = 0;
+        if self.unbonding_amount > 0 && self.unbonding_end <= now {
+            unbonded = self.unbonding_amount;
+            self.unbonding_amount = 0;
+        }
+        self.unbonding_amount = self.unbonding_amount.checked_add(amount).unwrap();
+        self.unbonding_end = now.checked_add(unbonding_period).unwrap();
+        unbonded
+    }
+    fn start_lock(&mut self, extra_account: &ExtraRewardsAccount, lock_duration: i64, now: i64) {
+        // a funder lock ends with the lock it was set on
+        if now >= self.last_stake_time.checked_add(self.lock_duration).unwrap() {
+            self.funder_locked = 0;
+        }
+        self.lock_duration = lock_duration;
+        self.last_stake_time = now;
+        self.extra_percentage = extra_account.get_extra_reward_percentage(&lock_duration);
+        self.config_version = extra_account.version;
+        self.penalty_enabled = extra_account.penalty.enabled as u8;
+        self.penalty_linear_decay = extra_account.penalty.linear_decay as u8;
+        self.penalty_principal_percentage = extra_account.penalty.principal_percentage;
+        self.penalty_extra_reward_percentage = extra_account.penalty.extra_reward_percentage;
+        self.penalty_destination = extra_account.penalty.destination;
+    }
+    // snapshotted terms hold until the lock ends, the current configs apply after;
+    // a settlement across the lock end is split pro rata by time
+    fn get_extra_percentage(&self, extra_account: &ExtraRewardsAccount, now: i64) -> u64 {
+        let lock_duration = self.lock_duration;
+        let lock_end = self.last_stake_time.checked_add(lock_duration).unwrap();
+        if now < lock_end {
+            return self.extra_percentage;
+        }
+        let current = extra_account.get_extra_reward_percentage(&lock_duration);
+        let last_reward_time = self.last_reward_time.max(self.last_stake_time);
+        if last_reward_time >= lock_end {
+            return current;
+        }
+        let locked_seconds = lock_end.checked_sub(last_reward_time).unwrap();
+        let unlocked_seconds = now.checked_sub(lock_end).unwrap();
+        u128::from(self.extra_percentage)
+            .checked_mul(u128::try_from(locked_seconds).unwrap())
+            .unwrap()
+            .checked_add(
+                u128::from(current)
+                    .checked_mul(u128::try_from(unlocked_seconds).unwrap())
+                    .unwrap(),
+            )
+            .unwrap()
+            .checked_div(u128::try_from(locked_seconds.checked_add(unlocked_seconds).unwrap()).unwrap())
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+    fn get_penalty_percentage(&self, percentage: u64, elapsed: i64) -> u64 {
+        let lock_duration = self.lock_duration;
+        if self.penalty_linear_decay == 0 || lock_duration <= 0 {
+            return percentage;
+        }
+        let remaining = lock_duration.checked_sub(elapsed).unwrap().max(0);
+        u128::from(percentage)
+            .checked_mul(u128::try_from(remaining).unwrap())
+            .unwrap()
+            .checked_div(u128::try_from(lock_duration).unwrap())
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
     fn calculate_reward_amount<'info>(
         &mut self,
         pool: &FarmPoolAccount,
         extra_percentage: &u64,
+        state: &mut StateAccount,
     ) -> Result<()> {
         let pending_amount: u128 = u128::from(self.amount)
             .checked_mul(pool.acc_reward_per_share)
@@ -710,12 +4034,17 @@ impl FarmPoolUserAccount {
             .checked_sub(self.reward_debt)
             .unwrap();
         self.reward_amount = self.reward_amount.checked_add(pending_amount).unwrap();
-        let extra_amount: u128 = pending_amount
+        // the base reward is committed by the pool update, the extra is committed here
+        let extra_amount: u64 = pending_amount
             .checked_mul(u128::from(*extra_percentage))
             .unwrap()
             .checked_div(u128::from(FULL_100))
+            .unwrap()
+            .min(u128::from(state.get_available_reward()))
+            .try_into()
             .unwrap();
-        self.extra_reward = self.extra_reward.checked_add(extra_amount).unwrap();
+        state.commit_reward(extra_amount);
+        self.extra_reward = self.extra_reward.checked_add(u128::from(extra_amount)).unwrap();
         Ok(())
     }
     fn calculate_reward_debt(&mut self, pool: &FarmPoolAccount) -> Result<()> {
@@ -725,13 +4054,147 @@ impl FarmPoolUserAccount {
         // msg!("multiplied {}", u128::from(self.amount).checked_mul(pool.acc_reward_per_share).unwrap());
         // msg!("scaled {}", u128::from(self.amount).checked_mul(pool.acc_reward_per_share).unwrap().checked_div(ACC_PRECISION).unwrap());
 
+        self.last_reward_time = pool.last_reward_time;
         self.reward_debt = u128::from(self.amount)
             .checked_mul(pool.acc_reward_per_share)
             .unwrap()
             .checked_div(ACC_PRECISION)
             .unwrap();
+        self.penalty_debt = u128::from(self.amount)
+            .checked_mul(pool.acc_penalty_per_share)
+            .unwrap()
+            .checked_div(ACC_PRECISION)
+            .unwrap();
+        Ok(())
+    }
+    fn calculate_stream_rewards(&mut self, streams: &[Loader<RewardStreamAccount>]) -> Result<()> {
+        let debts = self.stream_reward_debt;
+        let mut amounts = self.stream_reward_amount;
+        for (i, loader) in streams.iter().enumerate() {
+            let stream = loader.load()?;
+            let pending_amount = u128::from(self.amount)
+                .checked_mul(stream.acc_reward_per_share)
+                .unwrap()
+                .checked_div(ACC_PRECISION)
+                .unwrap()
+                .checked_sub(debts[i])
+                .unwrap();
+            amounts[i] = amounts[i].checked_add(pending_amount).unwrap();
+        }
+        self.stream_reward_amount = amounts;
+        Ok(())
+    }
+    fn calculate_stream_reward_debts(&mut self, streams: &[Loader<RewardStreamAccount>]) -> Result<()> {
+        let mut debts = self.stream_reward_debt;
+        for (i, loader) in streams.iter().enumerate() {
+            let stream = loader.load()?;
+            debts[i] = u128::from(self.amount)
+                .checked_mul(stream.acc_reward_per_share)
+                .unwrap()
+                .checked_div(ACC_PRECISION)
+                .unwrap();
+        }
+        self.stream_reward_debt = debts;
         Ok(())
     }
+    // emergency path, must not fail on broken reward math
+    fn get_penalty_share_unchecked(&self, pool: &FarmPoolAccount) -> u64 {
+        u128::from(self.amount)
+            .checked_mul(pool.acc_penalty_per_share)
+            .map(|v| v / ACC_PRECISION)
+            .and_then(|v| v.checked_sub(self.penalty_debt))
+            .and_then(|v| u64::try_from(v).ok())
+            .unwrap_or(0)
+    }
+    // zeroes pending rewards and returns the main reward commitment to release,
+    // the stream commitments are released in the streams right away
+    fn forfeit_rewards(
+        &mut self,
+        pool: &FarmPoolAccount,
+        streams: &[Loader<RewardStreamAccount>],
+    ) -> Result<u64> {
+        let pending_amount = u128::from(self.amount)
+            .checked_mul(pool.acc_reward_per_share)
+            .map(|v| v / ACC_PRECISION)
+            .and_then(|v| v.checked_sub(self.reward_debt))
+            .unwrap_or(0);
+        let forfeited = pending_amount
+            .saturating_add(self.reward_amount)
+            .saturating_add(self.extra_reward);
+        let debts = self.stream_reward_debt;
+        let amounts = self.stream_reward_amount;
+        for (i, loader) in streams.iter().enumerate() {
+            let mut stream = loader.load_mut()?;
+            let stream_pending = u128::from(self.amount)
+                .checked_mul(stream.acc_reward_per_share)
+                .map(|v| v / ACC_PRECISION)
+                .and_then(|v| v.checked_sub(debts[i]))
+                .unwrap_or(0)
+                .saturating_add(amounts[i]);
+            stream.release_reward(u64::try_from(stream_pending).unwrap_or(u64::MAX));
+        }
+        self.reward_amount = 0;
+        self.extra_reward = 0;
+        self.stream_reward_amount = [0; 8];
+        self.stream_reward_debt = [0; 8];
+        Ok(u64::try_from(forfeited).unwrap_or(u64::MAX))
+    }
+    // credits redistributed penalties to the position principal
+    fn claim_penalty_share(&mut self, pool: &FarmPoolAccount) -> Result<u64> {
+        let share: u64 = u128::from(self.amount)
+            .checked_mul(pool.acc_penalty_per_share)
+            .unwrap()
+            .checked_div(ACC_PRECISION)
+            .unwrap()
+            .checked_sub(self.penalty_debt)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        self.amount = self.amount.checked_add(share).unwrap();
+        Ok(share)
+    }
+}
+
+// the data of a legacy account between begin_migrate_* & finish_migrate_*
+#[account]
+pub struct MigrationAccount {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub account: Pubkey,
+    pub data: Vec<u8>, // without the discriminator
+} // 69 + 197
+
+// layouts of the first version
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyStateAccount {
+    pub authority: Pubkey,
+    pub reward_mint: Pubkey,
+    pub reward_vault: Pubkey,
+    pub bump: u8,
+    pub total_point: u64,
+    pub start_time: i64,
+    pub token_per_second: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyFarmPoolAccount {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub point: u64,
+    pub last_reward_time: i64,
+    pub acc_reward_per_share: u128,
+    pub amount_multipler: u64,
+    pub total_user: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyExtraRewardsAccount {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub configs: Vec<DurationExtraRewardConfig>,
 }
 
 #[error]
@@ -746,15 +4209,207 @@ pub enum ErrorCode {
     InvalidLockDuration,
     #[msg("Invalid SEQ")]
     InvalidSEQ,
+    #[msg("Invalid Position")]
+    InvalidPosition,
+    #[msg("Invalid Penalty")]
+    InvalidPenalty,
+    #[msg("Not under locked")]
+    NotUnderLocked,
+    #[msg("Invalid Amount")]
+    InvalidAmount,
+    #[msg("Unbonding required")]
+    UnbondingRequired,
+    #[msg("Unbonding not required")]
+    UnbondingNotRequired,
+    #[msg("Still unbonding")]
+    StillUnbonding,
+    #[msg("Invalid Unbonding Period")]
+    InvalidUnbondingPeriod,
+    #[msg("Invalid Schedule")]
+    InvalidSchedule,
+    #[msg("Invalid Rate Mode")]
+    InvalidRateMode,
+    #[msg("Invalid Stream")]
+    InvalidStream,
+    #[msg("Too many streams")]
+    TooManyStreams,
+    #[msg("Not all registered pools were updated")]
+    MissingPools,
+    #[msg("Too many pools")]
+    TooManyPools,
+    #[msg("Pool already registered")]
+    PoolAlreadyRegistered,
+    #[msg("Invalid State")]
+    InvalidState,
+    #[msg("Paused")]
+    Paused,
+    #[msg("Unstake paused")]
+    UnstakePaused,
+    #[msg("Not authorized to resume")]
+    NotAuthorizedToResume,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Timelock required")]
+    TimelockRequired,
+    #[msg("Timelock too short")]
+    TimelockTooShort,
+    #[msg("Timelock not ready")]
+    TimelockNotReady,
+    #[msg("Timelock too long")]
+    TimelockTooLong,
+    #[msg("Invalid Queued Change")]
+    InvalidQueuedChange,
+    #[msg("Queued change not found")]
+    QueuedChangeNotFound,
+    #[msg("Too many queued changes")]
+    TooManyQueuedChanges,
+    #[msg("Rate above max")]
+    RateTooHigh,
+    #[msg("Rate change above daily max")]
+    RateChangeTooHigh,
+    #[msg("Extra percentage above max")]
+    ExtraPercentageTooHigh,
+    #[msg("Lock duration above max")]
+    LockDurationTooLong,
+    #[msg("Pool can't compound")]
+    InvalidCompoundPool,
+    #[msg("Auto compound disabled")]
+    AutoCompoundDisabled,
+    #[msg("Auto harvest disabled")]
+    AutoHarvestDisabled,
+    #[msg("Invalid Keeper Tip")]
+    InvalidKeeperTip,
+    #[msg("Invalid Vesting")]
+    InvalidVesting,
+    #[msg("Vesting required")]
+    VestingRequired,
+    #[msg("Migration required")]
+    MigrationRequired,
+    #[msg("Nothing to migrate")]
+    NothingToMigrate,
+    #[msg("Invalid Migration")]
+    InvalidMigration,
+    #[msg("Migration pending")]
+    MigrationPending,
+    #[msg("Not paused")]
+    NotPaused,
+    #[msg("Legacy fixtures disabled")]
+    LegacyFixturesDisabled,
+}
+#[event]
+pub struct StateCreated {
+    state: Pubkey,
+    state_id: u64,
+    authority: Pubkey,
+    reward_mint: Pubkey,
+}
+#[event]
+pub struct RolesChanged {
+    state: Pubkey,
+    funder: Pubkey,
+    operator: Pubkey,
+    pauser: Pubkey,
+}
+#[event]
+pub struct CapsChanged {
+    state: Pubkey,
+    max_token_per_second: u64,
+    max_extra_percentage: u64,
+    max_rate_change_per_day: u64,
+    max_lock_duration: i64,
+}
+#[event]
+pub struct PauseChanged {
+    account: Pubkey,
+    authority: Pubkey,
+    paused: bool,
+    unstake_paused: bool,
+}
+#[event]
+pub struct TimelockDelayChanged {
+    state: Pubkey,
+    timelock_delay: i64,
+}
+#[event]
+pub struct ChangeQueued {
+    state: Pubkey,
+    id: u64,
+    kind: u8,
+    target: Pubkey,
+    value: u64,
+    eta: i64,
+}
+#[event]
+pub struct ChangeCancelled {
+    state: Pubkey,
+    id: u64,
+}
+#[event]
+pub struct ChangeExecuted {
+    state: Pubkey,
+    id: u64,
+    kind: u8,
+    target: Pubkey,
+}
+#[event]
+pub struct AuthorityProposed {
+    account: Pubkey,
+    authority: Pubkey,
+    pending_authority: Pubkey,
+}
+#[event]
+pub struct AuthorityProposalCancelled {
+    account: Pubkey,
+    authority: Pubkey,
+}
+#[event]
+pub struct AuthorityTransferred {
+    account: Pubkey,
+    previous_authority: Pubkey,
+    authority: Pubkey,
+}
+#[event]
+pub struct RewardFunded {
+    authority: Pubkey,
+    amount: u64,
+    total_funded: u64,
+}
+#[event]
+pub struct RewardRunway {
+    available: u64,
+    committed: u64,
+    token_per_second: u64,
+    runway: u64,
+}
+#[event]
+pub struct EmissionScheduleChanged {
+    end_time: i64,
+    steps: u64,
+    halving_interval: i64,
 }
 #[event]
 pub struct RateChanged {
     token_per_second: u64,
 }
 #[event]
+pub struct PoolRegistered {
+    pool: Pubkey,
+}
+#[event]
 pub struct PoolCreated {
     pool: Pubkey,
     mint: Pubkey,
+    pool_id: u64,
+}
+#[event]
+pub struct PoolExtraRewardConfigsCreated {
+    pool: Pubkey,
+    extra_reward_account: Pubkey,
+}
+#[event]
+pub struct ExtraRewardConfigsChanged {
+    extra_reward_account: Pubkey,
+    version: u64,
 }
 #[event]
 pub struct PoolLockDurationChanged {
@@ -762,9 +4417,32 @@ pub struct PoolLockDurationChanged {
     lock_duration: i64,
 }
 #[event]
-pub struct PoolAmountMultiplerChanged {
+pub struct PoolUnbondingPeriodChanged {
+    pool: Pubkey,
+    unbonding_period: i64,
+}
+#[event]
+pub struct PoolVestingChanged {
+    pool: Pubkey,
+    vesting_mode: u8,
+    vesting_period: i64,
+    vesting_early_claim_penalty: u64,
+}
+#[event]
+pub struct PoolKeeperTipChanged {
+    pool: Pubkey,
+    keeper_tip_percentage: u64,
+}
+#[event]
+pub struct PoolEmergencyUnlockChanged {
+    pool: Pubkey,
+    emergency_unlocked: bool,
+}
+#[event]
+pub struct PoolRateModeChanged {
     pool: Pubkey,
-    amount_multipler: u64,
+    rate_mode: u8,
+    token_per_second: u64,
 }
 #[event]
 pub struct PoolPointChanged {
@@ -772,12 +4450,55 @@ pub struct PoolPointChanged {
     point: u64,
 }
 #[event]
+pub struct RewardStreamCreated {
+    pool: Pubkey,
+    stream: Pubkey,
+    mint: Pubkey,
+    index: u8,
+    token_per_second: u64,
+    end_time: i64,
+}
+#[event]
+pub struct RewardStreamFunded {
+    stream: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+    total_funded: u64,
+}
+#[event]
+pub struct RewardStreamChanged {
+    stream: Pubkey,
+    token_per_second: u64,
+    end_time: i64,
+}
+#[event]
 pub struct UserCreated {
     pool: Pubkey,
     user: Pubkey,
     authority: Pubkey,
 }
 #[event]
+pub struct PositionCreated {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    index: u64,
+}
+#[event]
+pub struct AccountMigrated {
+    account: Pubkey,
+    authority: Pubkey,
+}
+#[event]
+pub struct UserMigrated {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+}
+#[event]
 pub struct UserEtherAddressChanged {
     authority: Pubkey,
     ether_address: String,
@@ -786,21 +4507,133 @@ pub struct UserEtherAddressChanged {
 pub struct UserStaked {
     pool: Pubkey,
     user: Pubkey,
+    position: Pubkey,
     authority: Pubkey,
     amount: u64,
     lock_duration: i64,
 }
 #[event]
+pub struct UserStakedFor {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    funder: Pubkey,
+    beneficiary: Pubkey,
+    amount: u64,
+    lock_duration: i64,
+}
+#[event]
 pub struct UserUnstaked {
     pool: Pubkey,
     user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+}
+#[event]
+pub struct UserUnstakeRequested {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+    unbonding_end: i64,
+}
+#[event]
+pub struct UserUnbondedWithdrawn {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+}
+#[event]
+pub struct UserEmergencyWithdrawn {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+    forfeited_reward: u64,
+}
+#[event]
+pub struct UserEarlyUnstaked {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
     authority: Pubkey,
     amount: u64,
+    principal_penalty: u64,
+    extra_reward_penalty: u64,
+    destination: u8,
 }
 #[event]
 pub struct UserHarvested {
     pool: Pubkey,
     user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+}
+#[event]
+pub struct UserCompounded {
+    pool: Pubkey,
+    user: Pubkey,
+    position: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+}
+#[event]
+pub struct DelegateChanged {
+    user: Pubkey,
+    delegate: Pubkey,
+}
+#[event]
+pub struct AutoCompoundChanged {
+    user: Pubkey,
+    auto_compound: bool,
+}
+#[event]
+pub struct AutoHarvestChanged {
+    user: Pubkey,
+    auto_harvest: bool,
+}
+#[event]
+pub struct KeeperTipPaid {
+    pool: Pubkey,
+    position: Pubkey,
+    keeper: Pubkey,
+    amount: u64,
+}
+#[event]
+pub struct RewardVested {
+    pool: Pubkey,
+    position: Pubkey,
+    vesting: Pubkey,
+    amount: u64,
+    end_time: i64,
+}
+#[event]
+pub struct VestedClaimed {
+    pool: Pubkey,
+    vesting: Pubkey,
+    authority: Pubkey,
+    amount: u64,
+    penalty: u64,
+}
+#[event]
+pub struct UserHarvestedAll {
+    pool: Pubkey,
+    user: Pubkey,
+    authority: Pubkey,
+    positions: u64,
+    amount: u64,
+}
+#[event]
+pub struct UserStreamHarvested {
+    pool: Pubkey,
+    stream: Pubkey,
+    position: Pubkey,
     authority: Pubkey,
     amount: u64,
 }
@@ -20,8 +20,9 @@ const MAIN_POOL_CONFIG = {
   PROGRAM_ID: new PublicKey('GfXYYi5TFPG5ixdfiXLQacgjZbatqpb9uZZPMTBxMVCx'),
   REWARD_TOKEN_ID: new PublicKey('BcRr96qhSoaKFjGJDKtSWmHDvTrv7ziuq29dRjtUmHuk'),
   FUND_TOKEN_VAULT: new PublicKey('C93xejNSwRKRs4ro9WMSr5iKZCeRhgQMztK2kzm8rth3'),
+  STATE_ID: new BN(0), // 0 => the first staking state of the program, seeded by "state" only
+  POOL_ID: new BN(0), // 0 => the first pool of the mint, seeded by the mint only
   POOL_POINT: new BN('0'),
-  POOL_AMOUNT_MULTIPLIER: new BN('1'),
   STAKING_RATE: new BN(0.10 * 1_000_000),
   FUND_AMOUNT: new BN(1000000 * 1_000_000), // 1000 NNI token
   REWARD_CONFIGS: [
@@ -54,32 +55,57 @@ async function getStateAccount() {
   return {publicKey: stateSigner, rewardVault, startTime, tokenPerSecond}
 }
 async function getStateSigner () {
-  const [_poolSigner,] = await anchor.web3.PublicKey.findProgramAddress(
-    [utf8.encode('state')],
-    program.programId
-  );
+  const [_poolSigner,] = await getStateAddress()
   return _poolSigner
 }
-async function getPoolSigner () {
-  const [_poolSigner,] = await anchor.web3.PublicKey.findProgramAddress(
-    [STAKING_CONFIG.REWARD_TOKEN_ID.toBuffer()],
+function getIdSeeds (id) {
+  return id.isZero() ? [] : [id.toArrayLike(Buffer, 'le', 8)]
+}
+async function getStateAddress () {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [utf8.encode('state'), ...getIdSeeds(STAKING_CONFIG.STATE_ID)],
     program.programId
   );
+}
+async function getPoolSigner () {
+  const [_poolSigner,] = await getPoolAddress()
   return _poolSigner
 }
+async function getPoolAddress () {
+  const seeds = [STAKING_CONFIG.REWARD_TOKEN_ID.toBuffer(), ...getIdSeeds(STAKING_CONFIG.POOL_ID)]
+  if (!STAKING_CONFIG.STATE_ID.isZero()) {
+    seeds.push((await getStateSigner()).toBuffer())
+  }
+  return await anchor.web3.PublicKey.findProgramAddress(seeds, program.programId)
+}
 async function getRewardConfigSigner () {
-  const [_poolSigner,] = await anchor.web3.PublicKey.findProgramAddress(
-    [utf8.encode('extra')],
+  const [_poolSigner,] = await getRewardConfigAddress()
+  return _poolSigner
+}
+async function getRewardConfigAddress () {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [utf8.encode('extra'), ...getIdSeeds(STAKING_CONFIG.STATE_ID)],
     program.programId
   );
-  return _poolSigner
+}
+async function getRegistrySigner () {
+  const [_registrySigner,] = await anchor.web3.PublicKey.findProgramAddress(
+    [utf8.encode('registry'), (await getStateSigner()).toBuffer()],
+    program.programId
+  );
+  return _registrySigner
+}
+// the pools of this state only, as the mass update expects them
+async function getRegisteredPools () {
+  const { pools } = await program.account.poolRegistryAccount.fetch(await getRegistrySigner())
+  return pools
 }
 async function getAssociatedTokenAddress (mintAddress, owner) {
   return await Token.getAssociatedTokenAddress(ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, mintAddress, owner, true)
 }
 
 const utils = {
-  getNumber, getStateSigner, getPoolSigner, getAssociatedTokenAddress, getStateAccount, getRewardConfigSigner
+  getNumber, getStateSigner, getStateAddress, getPoolSigner, getPoolAddress, getAssociatedTokenAddress, getStateAccount, getRewardConfigSigner, getRewardConfigAddress, getRegistrySigner, getRegisteredPools
 }
 
 module.exports = {
@@ -11,20 +11,21 @@ const { ENV_CONFIG, utils, STAKING_CONFIG } = require('./CONFIG')
 const { program, provider } = ENV_CONFIG
 
 async function main () {
-  let pools = await program.account.farmPoolAccount.all()
+  let pools = await utils.getRegisteredPools()
   const stateAccount = await utils.getStateAccount()
   await program.rpc.changePoolPoint(STAKING_CONFIG.POOL_POINT, {
     accounts: {
       pool: await utils.getPoolSigner(),
       state: stateAccount.publicKey,
+      registry: await utils.getRegistrySigner(),
       mint: STAKING_CONFIG.REWARD_TOKEN_ID,
       authority: provider.wallet.publicKey,
       tokenProgram: TOKEN_PROGRAM_ID,
       clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
       systemProgram: SystemProgram.programId,
     },
-    remainingAccounts: pools.map(p => ({
-      pubkey: p.publicKey,
+    remainingAccounts: pools.map(pubkey => ({
+      pubkey,
       isWritable: true,
       isSigner: false
     }))
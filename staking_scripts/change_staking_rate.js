@@ -11,17 +11,18 @@ const { ENV_CONFIG, utils, STAKING_CONFIG } = require('./CONFIG')
 const { program, provider } = ENV_CONFIG
 
 async function main () {
-  let pools = await program.account.farmPoolAccount.all()
+  let pools = await utils.getRegisteredPools()
   await program.rpc.changeTokensPerSecond(STAKING_CONFIG.STAKING_RATE, {
     accounts: {
       state: await utils.getStateSigner(),
+      registry: await utils.getRegistrySigner(),
       authority: provider.wallet.publicKey,
       tokenProgram: TOKEN_PROGRAM_ID,
       clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
       systemProgram: SystemProgram.programId,
     },
-    remainingAccounts: pools.map(p => ({
-      pubkey: p.publicKey,
+    remainingAccounts: pools.map(pubkey => ({
+      pubkey,
       isWritable: true,
       isSigner: false
     }))
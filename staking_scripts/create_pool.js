@@ -12,16 +12,14 @@ const { program, provider } = ENV_CONFIG
 
 async function main () {
   const stateAccount = await utils.getStateAccount()
-  const [poolSigner, poolBump] = await anchor.web3.PublicKey.findProgramAddress(
-    [STAKING_CONFIG.REWARD_TOKEN_ID.toBuffer()],
-    program.programId
-  );
+  const [poolSigner, poolBump] = await utils.getPoolAddress()
   const poolVault = await ENV_CONFIG.rewardToken.createAccount(poolSigner)
-  let pools = await program.account.farmPoolAccount.all()
-  await program.rpc.createPool(poolBump, STAKING_CONFIG.POOL_POINT, STAKING_CONFIG.POOL_AMOUNT_MULTIPLIER, {
+  let pools = await utils.getRegisteredPools()
+  await program.rpc.createPool(poolBump, STAKING_CONFIG.POOL_ID, STAKING_CONFIG.POOL_POINT, {
     accounts: {
       pool: poolSigner,
       state: stateAccount.publicKey,
+      registry: await utils.getRegistrySigner(),
       mint: STAKING_CONFIG.REWARD_TOKEN_ID,
       vault: poolVault,
       authority: provider.wallet.publicKey,
@@ -29,8 +27,8 @@ async function main () {
       clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
       systemProgram: SystemProgram.programId,
     },
-    remainingAccounts: pools.map(p => ({
-      pubkey: p.publicKey,
+    remainingAccounts: pools.map(pubkey => ({
+      pubkey,
       isWritable: true,
       isSigner: false
     }))
@@ -11,14 +11,12 @@ const { ENV_CONFIG, utils, STAKING_CONFIG } = require('./CONFIG')
 const { program, provider } = ENV_CONFIG
 
 async function main () {
-  const [extraRewardAccount, extraRewardBump] = await anchor.web3.PublicKey.findProgramAddress(
-    [utf8.encode('extra')],
-    program.programId
-  );
+  const [extraRewardAccount, extraRewardBump] = await utils.getRewardConfigAddress()
   await program.rpc.createExtraRewardConfigs(extraRewardBump, STAKING_CONFIG.REWARD_CONFIGS,
   {
       accounts: {
         extraRewardAccount: extraRewardAccount,
+        state: await utils.getStateSigner(),
         authority: provider.wallet.publicKey,
         clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
         systemProgram: SystemProgram.programId,
@@ -12,22 +12,36 @@ const { program, provider } = ENV_CONFIG
 
 async function main () {
   const {rewardToken} = ENV_CONFIG
-  const [stateSigner, stateBump] = await anchor.web3.PublicKey.findProgramAddress(
-    [utf8.encode('state')],
+  const [stateSigner, stateBump] = await utils.getStateAddress()
+  const stateRewardVault = await rewardToken.createAccount(stateSigner)
+  const [migrationSigner] = await anchor.web3.PublicKey.findProgramAddress(
+    [utf8.encode('migration'), stateSigner.toBuffer()],
     program.programId
   );
-  const stateRewardVault = await rewardToken.createAccount(stateSigner)
-  await program.rpc.createState(stateBump, STAKING_CONFIG.STAKING_RATE, {
+  await program.rpc.createState(stateBump, STAKING_CONFIG.STATE_ID, STAKING_CONFIG.STAKING_RATE, {
     accounts: {
       state: stateSigner,
       rewardMint: rewardToken.publicKey,
       rewardVault: stateRewardVault,
+      migration: migrationSigner,
       authority: provider.wallet.publicKey,
       tokenProgram: TOKEN_PROGRAM_ID,
       clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
       systemProgram: SystemProgram.programId,
     }
   })
+  const [registrySigner, registryBump] = await anchor.web3.PublicKey.findProgramAddress(
+    [utf8.encode('registry'), stateSigner.toBuffer()],
+    program.programId
+  );
+  await program.rpc.createPoolRegistry(registryBump, {
+    accounts: {
+      registry: registrySigner,
+      state: stateSigner,
+      authority: provider.wallet.publicKey,
+      systemProgram: SystemProgram.programId,
+    }
+  })
   const stateInfo = await program.account.stateAccount.fetch(stateSigner)
   console.log(stateInfo)
 }
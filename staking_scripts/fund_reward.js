@@ -14,7 +14,6 @@ async function main () {
   const stateAccount = await utils.getStateAccount()
   await program.rpc.fundRewardToken(STAKING_CONFIG.FUND_AMOUNT, {
     accounts: {
-      pool: await utils.getPoolSigner(),
       state: stateAccount.publicKey,
       rewardVault: stateAccount.rewardVault,
       userVault: STAKING_CONFIG.FUND_TOKEN_VAULT,
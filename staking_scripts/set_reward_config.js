@@ -15,6 +15,7 @@ async function main () {
   {
       accounts: {
         extraRewardAccount: await utils.getRewardConfigSigner(),
+        state: await utils.getStateSigner(),
         authority: provider.wallet.publicKey,
         clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
         systemProgram: SystemProgram.programId,
@@ -5,6 +5,7 @@ const _ = require('lodash')
 const { BN, web3, Program, ProgramError, Provider } = anchor
 const { PublicKey, SystemProgram, Keypair, Transaction } = web3
 const assert = require("assert");
+const crypto = require('crypto')
 const { assertError, wrapError } = require("./utils")
 const utf8 = anchor.utils.bytes.utf8;
 const provider = anchor.Provider.local()
@@ -17,6 +18,9 @@ let stateSigner = Keypair.generate().publicKey
 let stateBump = 255
 let stateRewardVault = Keypair.generate().publicKey
 
+let registrySigner = Keypair.generate().publicKey
+let registryBump = 255
+
 let extraRewardSigner = Keypair.generate().publicKey
 let extraRewardBump = 255
 
@@ -29,6 +33,8 @@ let lpMint = new Token()
 let lpPoolSigner = Keypair.generate().publicKey
 let lpPoolVault = Keypair.generate().publicKey
 let lpPoolBump = 255
+let lpExtraRewardSigner = Keypair.generate().publicKey
+let lpExtraRewardBump = 255
 
 
 anchor.setProvider(provider);
@@ -54,8 +60,11 @@ const users = _.map(new Array(10), () => {
   const etherAddressBump = 255
   const lpBump = 255
 
+  const positionAccounts = []
+  const lpPositionAccounts = []
+
   const joinedPool = false
-  return { lastHarvestTime1: 0, etherAddressAccount, etherAddressBump, user, publicKey, wallet, provider, userAccount1, bump1, rewardUserVault, rewardAmount, lpUserVault, joinedPool, lpUserAccount, lpBump, lpLastHarvestTime: 0 }
+  return { lastHarvestTime1: 0, etherAddressAccount, etherAddressBump, user, publicKey, wallet, provider, userAccount1, bump1, rewardUserVault, rewardAmount, lpUserVault, joinedPool, lpUserAccount, lpBump, lpLastHarvestTime: 0, positionAccounts, lpPositionAccounts }
 })
 
 const [master, user1, user2, user3, user4, userLP1, userLP2, ...otherUsers] = users
@@ -74,6 +83,10 @@ describe('staking', () => {
       [utf8.encode('state')],
       program.programId
     );
+    [registrySigner, registryBump] = await anchor.web3.PublicKey.findProgramAddress(
+      [utf8.encode('registry'), stateSigner.toBuffer()],
+      program.programId
+    );
     [extraRewardSigner, extraRewardBump] = await anchor.web3.PublicKey.findProgramAddress(
       [utf8.encode('extra')],
       program.programId
@@ -86,6 +99,10 @@ describe('staking', () => {
       [lpMint.publicKey.toBuffer()],
       program.programId
     );
+    [lpExtraRewardSigner, lpExtraRewardBump] = await anchor.web3.PublicKey.findProgramAddress(
+      [utf8.encode('extra'), lpPoolSigner.toBuffer()],
+      program.programId
+    );
     stateRewardVault = await rewardMint.createAccount(stateSigner)
     poolVault = await rewardMint.createAccount(poolSigner)
     lpPoolVault = await lpMint.createAccount(lpPoolSigner)
@@ -120,11 +137,13 @@ describe('staking', () => {
     await lpMint.mintTo(userLP2.lpUserVault, creatorKey, [provider.wallet], new BN(100).toString())
   })
   it('Create State', async function () {
-    await program.rpc.createState(stateBump, new BN(20), {
+    const [migration] = await getMigrationAddress(stateSigner)
+    await program.rpc.createState(stateBump, new BN(0), new BN(20), {
       accounts: {
         state: stateSigner,
         rewardMint: rewardMint.publicKey,
         rewardVault: stateRewardVault,
+        migration,
         authority: creatorKey,
         ...defaultAccounts
       }
@@ -132,12 +151,25 @@ describe('staking', () => {
     const stateInfo = await program.account.stateAccount.fetch(stateSigner)
     assert.ok(stateInfo.tokenPerSecond.eq(new BN(20)))
   })
+  it('Create Pool Registry', async function () {
+    await program.rpc.createPoolRegistry(registryBump, {
+      accounts: {
+        registry: registrySigner,
+        state: stateSigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    const registryInfo = await program.account.poolRegistryAccount.fetch(registrySigner)
+    assert.ok(registryInfo.pools.length === 0)
+  })
   it('Create ExtraReward', async function () {
     await program.rpc.createExtraRewardConfigs(extraRewardBump, [
       { duration: new BN(0), extraPercentage: getNumber(0) },
     ], {
       accounts: {
         extraRewardAccount: extraRewardSigner,
+        state: stateSigner,
         authority: creatorKey,
         ...defaultAccounts
       },
@@ -149,6 +181,7 @@ describe('staking', () => {
     ], {
       accounts: {
         extraRewardAccount: extraRewardSigner,
+        state: stateSigner,
         authority: creatorKey,
         ...defaultAccounts
       },
@@ -161,10 +194,11 @@ describe('staking', () => {
   
   it('Create Pool', async function () {
     let pools = await program.account.farmPoolAccount.all()
-    await program.rpc.createPool(poolBump, new BN('0'), new BN('0'), {
+    await program.rpc.createPool(poolBump, new BN(0), new BN('0'), {
       accounts: {
         pool: poolSigner,
         state: stateSigner,
+        registry: registrySigner,
         mint: rewardMint.publicKey,
         vault: poolVault,
         authority: creatorKey,
@@ -181,6 +215,7 @@ describe('staking', () => {
       accounts: {
         pool: poolSigner,
         state: stateSigner,
+        registry: registrySigner,
         authority: creatorKey,
         ...defaultAccounts
       },
@@ -191,10 +226,11 @@ describe('staking', () => {
       }))
     })
     pools = await program.account.farmPoolAccount.all()
-    await program.rpc.createPool(poolBump, new BN('0'), new BN('0'), {
+    await program.rpc.createPool(poolBump, new BN(0), new BN('0'), {
       accounts: {
         pool: poolSigner,
         state: stateSigner,
+        registry: registrySigner,
         mint: rewardMint.publicKey,
         vault: poolVault,
         authority: creatorKey,
@@ -210,13 +246,18 @@ describe('staking', () => {
     let poolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
     assert.ok(poolInfo.point.eq(stateInfo.totalPoint))
     assert.ok(poolInfo.point.eq(new BN('0')))
-    assert.ok(poolInfo.amountMultipler.eq(new BN(0)))
   })
   it('Fund to program', async function () {
     // await rewardMint.mintTo(stateRewardVault, creatorKey, [provider.wallet], getNumber(10000).toString())
+    // master funds rewards without being the admin
+    await program.rpc.setRoles(master.publicKey, PublicKey.default, PublicKey.default, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
     const tx = program.transaction.fundRewardToken(new BN(10000), {
       accounts: {
-        pool: poolSigner,
         state: stateSigner,
         rewardVault: stateRewardVault,
         userVault: master.rewardUserVault,
@@ -225,18 +266,11 @@ describe('staking', () => {
       }
     })
     await master.provider.send(tx, [], {})
-  })
-  it('changePoolAmountMultipler', async function () {
-    await program.rpc.changePoolAmountMultipler(new BN(1), {
-      accounts: {
-        pool: poolSigner,
-        state: stateSigner,
-        authority: creatorKey,
-        ...defaultAccounts
-      }
-    })
-    let poolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
-    assert.ok(poolInfo.amountMultipler.eq(new BN(1)))
+    const stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    const poolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
+    assert.ok(stateInfo.totalFunded.eq(new BN(10000)))
+    assert.ok(stateInfo.totalPoint.eq(new BN(0)))
+    assert.ok(poolInfo.point.eq(new BN(0)))
   })
   it('changePoolPoint', async function () {
     let pools = await program.account.farmPoolAccount.all()
@@ -244,6 +278,7 @@ describe('staking', () => {
       accounts: {
         pool: poolSigner,
         state: stateSigner,
+        registry: registrySigner,
         authority: creatorKey,
         ...defaultAccounts
       },
@@ -260,10 +295,11 @@ describe('staking', () => {
   })
   it('Create Pool LP', async function () {
     let pools = await program.account.farmPoolAccount.all()
-    await wrapError(program.rpc.createPool(lpPoolBump, new BN('1000'), new BN('1000'), {
+    await wrapError(program.rpc.createPool(lpPoolBump, new BN(0), new BN('1000'), {
       accounts: {
         pool: lpPoolSigner,
         state: stateSigner,
+        registry: registrySigner,
         mint: lpMint.publicKey,
         vault: lpPoolVault,
         authority: creatorKey,
@@ -279,7 +315,72 @@ describe('staking', () => {
     let poolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
     assert.ok(stateInfo.totalPoint.eq(new BN('2000')))
     assert.ok(poolInfo.point.eq(new BN('1000')))
-    assert.ok(poolInfo.amountMultipler.eq(new BN(1)))
+  })
+  it('Pool registry', async function () {
+    const registryInfo = await program.account.poolRegistryAccount.fetch(registrySigner)
+    assert.ok(registryInfo.pools.length === 2)
+
+    // skipping a registered pool is rejected
+    await assertError(program.rpc.changeTokensPerSecond(new BN(20), {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts: [{ pubkey: poolSigner, isWritable: true, isSigner: false }]
+    }), 'Not all registered pools were updated')
+    await assertError(program.rpc.registerPool({
+      accounts: {
+        registry: registrySigner,
+        state: stateSigner,
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    }), 'Pool already registered')
+  })
+  it('Create seasonal pool on the same mint', async function () {
+    const poolId = new BN(1)
+    const [seasonalPoolSigner, seasonalPoolBump] = await PublicKey.findProgramAddress([
+      rewardMint.publicKey.toBuffer(), poolId.toArrayLike(Buffer, 'le', 8)
+    ], program.programId)
+    const seasonalPoolVault = await rewardMint.createAccount(seasonalPoolSigner)
+    await program.rpc.createPool(seasonalPoolBump, poolId, new BN(0), {
+      accounts: {
+        pool: seasonalPoolSigner,
+        state: stateSigner,
+        registry: registrySigner,
+        mint: rewardMint.publicKey,
+        vault: seasonalPoolVault,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    const poolInfo = await program.account.farmPoolAccount.fetch(seasonalPoolSigner)
+    assert.ok(poolInfo.poolId.eq(poolId))
+    assert.ok(poolInfo.mint.equals(rewardMint.publicKey))
+    // pool 0 stays on the legacy [mint] address
+    const legacyPoolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
+    assert.ok(legacyPoolInfo.poolId.eq(new BN(0)))
+  })
+  it('Create ExtraReward LP', async function () {
+    await program.rpc.createPoolExtraRewardConfigs(lpExtraRewardBump, [
+      { duration: new BN(0), extraPercentage: getNumber(0) },
+      { duration: new BN(10), extraPercentage: getNumber(20) },
+    ], {
+      accounts: {
+        extraRewardAccount: lpExtraRewardSigner,
+        pool: lpPoolSigner,
+        state: stateSigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+    })
+    const extraRewardConfigs = await program.account.extraRewardsAccount.fetch(lpExtraRewardSigner)
+    const poolInfo = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    assert.ok(extraRewardConfigs.configs.length === 2)
+    assert.ok(extraRewardConfigs.pool.equals(lpPoolSigner))
+    assert.ok(poolInfo.extraRewardAccount.equals(lpExtraRewardSigner))
   })
   it('Create User', async function () {
     await Promise.all(users.map(u => wrapError(async () => {
@@ -303,6 +404,8 @@ describe('staking', () => {
         }
       })
       await u.provider.send(tx2, [], { skipPreflight: true })
+      await createPosition(u)
+      await createPositionLP(u)
     })))
     console.log((await await program.account.farmPoolAccount.fetch(poolSigner)).totalUser.toString())
   })
@@ -363,6 +466,10 @@ describe('staking', () => {
       assert.ok(new BN(t1.blockTime).eq(userInfo.lastStakeTime))
     })
   })
+  it('StakeLP with global tiers', async function () {
+    await assertError(stakeLP(userLP1, new BN(100), 0, extraRewardSigner), 'A raw constraint was violated')
+    await assertError(stakeLP(userLP1, new BN(100), 1), 'Invalid Lock Duration')
+  })
   it('StakeLP', async function () {
     const [t1, t2] = await Promise.all([
       stakeLP(userLP1, new BN(100)),
@@ -415,6 +522,7 @@ describe('staking', () => {
     const [t1] = await Promise.all([harvest(user1), harvest(user2), harvestLP(userLP1), harvestLP(userLP2), program.rpc.changeTokensPerSecond(new BN(40), {
       accounts: {
         state: stateSigner,
+        registry: registrySigner,
         authority: creatorKey,
         ...defaultAccounts
       },
@@ -489,6 +597,94 @@ describe('staking', () => {
     const reward5 = (t6.blockTime - t5.blockTime) * 20 * 1.6
     await assertUserReward(user4, reward1 + reward2 + reward3 + reward4 + reward5)
   })
+  it('Multiple positions', async function () {
+    await rewardMint.mintTo(user4.rewardUserVault, creatorKey, [provider.wallet], new BN(100).toString())
+    await createPosition(user4)
+    await stake(user4, new BN(100), 2, 1)
+
+    const userInfo = await program.account.farmPoolUserAccount.fetch(user4.userAccount1)
+    const position0 = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[0])
+    const position1 = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[1])
+    assert.ok(userInfo.positionCount.eq(new BN(2)))
+    assert.ok(position0.lockDuration.eq(new BN(2)))
+    assert.ok(position1.amount.eq(new BN(100)))
+    assert.ok(position1.index.eq(new BN(1)))
+    assert.ok(userInfo.amount.eq(position0.amount.add(position1.amount)))
+
+    await harvestAll(user4)
+    await assertUserReward(user4, 0, false)
+  })
+  it('Early unstake', async function () {
+    await program.rpc.setExtraRewardConfigs([
+      { duration: new BN(0), extraPercentage: getNumber(0) },
+      { duration: new BN(1), extraPercentage: getNumber(50) },
+      { duration: new BN(2), extraPercentage: getNumber(100) },
+      { duration: new BN(1000), extraPercentage: getNumber(100) },
+    ], {
+      accounts: {
+        extraRewardAccount: extraRewardSigner,
+        state: stateSigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+    })
+    const treasuryVault = await rewardMint.createAccount(creatorKey)
+    await assertError(earlyUnstake(user4, new BN(100), 1, treasuryVault), 'Under locked')
+
+    await program.rpc.setEarlyUnstakePenalty({
+      enabled: true,
+      principalPercentage: getNumber(10),
+      extraRewardPercentage: getNumber(100),
+      linearDecay: false,
+      destination: 0,
+      treasury: creatorKey,
+    }, {
+      accounts: {
+        extraRewardAccount: extraRewardSigner,
+        authority: creatorKey,
+      },
+    })
+    await rewardMint.mintTo(user4.rewardUserVault, creatorKey, [provider.wallet], new BN(100).toString())
+    await createPosition(user4)
+    await stake(user4, new BN(100), 1000, 2)
+    const extraRewardConfigs = await program.account.extraRewardsAccount.fetch(extraRewardSigner)
+    const lockedPosition = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[2])
+    assert.ok(lockedPosition.configVersion.eq(extraRewardConfigs.version))
+    assert.ok(lockedPosition.extraPercentage.eq(getNumber(100)))
+    assert.ok(lockedPosition.penaltyPrincipalPercentage.eq(getNumber(10)))
+
+    await earlyUnstake(user4, new BN(100), 2, treasuryVault)
+
+    const position = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[2])
+    assert.ok(position.amount.eq(new BN(0)))
+    assert.ok(position.extraReward.eq(new BN(0)))
+    assert.ok((await getTokenAmount(treasuryVault)).eq(new BN(10)))
+
+    // a burned penalty needs no treasury, any token account fills the slot
+    const setPenalty = (destination, treasury) => program.rpc.setEarlyUnstakePenalty({
+      enabled: true,
+      principalPercentage: getNumber(10),
+      extraRewardPercentage: getNumber(100),
+      linearDecay: false,
+      destination,
+      treasury,
+    }, {
+      accounts: {
+        extraRewardAccount: extraRewardSigner,
+        authority: creatorKey,
+      },
+    })
+    await setPenalty(2, PublicKey.default)
+    await rewardMint.mintTo(user3.rewardUserVault, creatorKey, [provider.wallet], new BN(100).toString())
+    await createPosition(user3)
+    const index = user3.positionAccounts.length - 1
+    await stake(user3, new BN(100), 1000, index)
+    const supplyBefore = (await rewardMint.getMintInfo()).supply
+    await earlyUnstake(user3, new BN(100), index, user3.rewardUserVault)
+    const supplyAfter = (await rewardMint.getMintInfo()).supply
+    assert.ok(supplyBefore.sub(supplyAfter).eq(new BN(10)))
+    await setPenalty(0, creatorKey)
+  })
   it('Check LP POOL', async function () {
     // set point POOL 1 to ZERO
     let pools = await program.account.farmPoolAccount.all()
@@ -496,6 +692,7 @@ describe('staking', () => {
       accounts: {
         pool: poolSigner,
         state: stateSigner,
+        registry: registrySigner,
         authority: creatorKey,
         ...defaultAccounts
       },
@@ -528,125 +725,1728 @@ describe('staking', () => {
     await assertUserReward(userLP1, userLP1.rewardAmount.add(new BN(10 * (tran1.blockTime - userLP1.lpLastHarvestTime) + 20 * (txLP.blockTime - tran1.blockTime))))
     await assertUserReward(userLP2, userLP2.rewardAmount.add(new BN(10 * (tran1.blockTime - userLP2.lpLastHarvestTime) + 20 * (txLP.blockTime - tran1.blockTime))))
   })
-})
+  it('Unbonding', async function () {
+    await program.rpc.changePoolUnbondingPeriod(new BN(2), {
+      accounts: {
+        pool: lpPoolSigner,
+        authority: creatorKey,
+      }
+    })
+    await assertError(unstakeLP(userLP1, new BN(50)), 'Unbonding required')
 
-async function guardTime (time, fn) {
-  let completed = false
-  let tooShort = false
-  await Promise.all([fn().then(() => completed = true), sleep(time).then(() => tooShort = !completed)])
-  if (tooShort) console.error('SHORT')
-}
+    const poolBefore = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    await requestUnstakeLP(userLP1, new BN(25))
+    let position = await program.account.farmPoolPositionAccount.fetch(userLP1.lpPositionAccounts[0])
+    const firstUnbondingEnd = position.unbondingEnd
+    // a second request restarts the cooldown for the whole unbonding balance
+    await sleep(1500)
+    await requestUnstakeLP(userLP1, new BN(25))
+    const poolAfter = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    position = await program.account.farmPoolPositionAccount.fetch(userLP1.lpPositionAccounts[0])
+    assert.ok(poolBefore.amount.sub(poolAfter.amount).eq(new BN(50)))
+    assert.ok(position.unbondingAmount.eq(new BN(50)))
+    assert.ok(position.unbondingEnd.gt(firstUnbondingEnd))
+    await assertError(withdrawUnbondedLP(userLP1), 'Still unbonding')
 
-async function assertUserReward (user, amount, showthrow = true) {
-  const realAmount = await getTokenAmount(user.rewardUserVault)
-  user.rewardAmount = realAmount
-  if (showthrow)
-    assert.ok(new BN(amount.toString()).eq(realAmount), `Expected ${amount.toString()} but got ${realAmount.toString()}`)
-}
+    await sleep(3000)
+    const vaultBefore = await getTokenAmount(userLP1.lpUserVault)
+    await withdrawUnbondedLP(userLP1)
+    const vaultAfter = await getTokenAmount(userLP1.lpUserVault)
+    position = await program.account.farmPoolPositionAccount.fetch(userLP1.lpPositionAccounts[0])
+    assert.ok(vaultAfter.sub(vaultBefore).eq(new BN(50)))
+    assert.ok(position.unbondingAmount.eq(new BN(0)))
 
-async function unstake (u, amount) {
-  const tx = program.transaction.unstake(amount, {
-    accounts: {
-      mint: rewardMint.publicKey,
-      extraRewardAccount: extraRewardSigner,
-      poolVault: poolVault,
-      userVault: u.rewardUserVault,
-      user: u.userAccount1,
-      state: stateSigner,
-      pool: poolSigner,
-      authority: u.publicKey,
-      ...defaultAccounts
-    }
-  });
-  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
-  return await cccc.getTransaction(hash)
-}
+    // a balance that has cooled down is paid out before a new request restarts the cooldown,
+    // and a request leaves the lock of the remaining stake as it is
+    await requestUnstakeLP(userLP1, new BN(20))
+    await sleep(3000)
+    const lastStakeTime = position.lastStakeTime
+    const vaultBeforeMatured = await getTokenAmount(userLP1.lpUserVault)
+    await requestUnstakeLP(userLP1, new BN(10))
+    const vaultAfterMatured = await getTokenAmount(userLP1.lpUserVault)
+    position = await program.account.farmPoolPositionAccount.fetch(userLP1.lpPositionAccounts[0])
+    assert.ok(vaultAfterMatured.sub(vaultBeforeMatured).eq(new BN(20)))
+    assert.ok(position.unbondingAmount.eq(new BN(10)))
+    assert.ok(position.lastStakeTime.eq(lastStakeTime))
+  })
+  it('Reward solvency', async function () {
+    await program.rpc.getRewardRunway({
+      accounts: {
+        state: stateSigner,
+        ...defaultAccounts
+      }
+    })
+    const stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    const rewardVaultAmount = await getTokenAmount(stateRewardVault)
+    assert.ok(stateInfo.totalFunded.sub(stateInfo.totalDistributed).eq(rewardVaultAmount))
+    assert.ok(stateInfo.rewardCommitted.lte(rewardVaultAmount))
+  })
+  it('Emission schedule', async function () {
+    const pools = await program.account.farmPoolAccount.all()
+    const setSchedule = (endTime, steps, halvingInterval) => program.rpc.setEmissionSchedule(endTime, steps, halvingInterval, {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts: pools.map(p => ({
+        pubkey: p.publicKey,
+        isWritable: true,
+        isSigner: false
+      }))
+    })
+    await assertError(setSchedule(new BN(0), [
+      { startTime: new BN(20), tokenPerSecond: new BN(10) },
+      { startTime: new BN(10), tokenPerSecond: new BN(5) },
+    ], new BN(0)), 'Invalid Schedule')
 
-async function harvest (u) {
-  const tx = program.transaction.harvest({
-    accounts: {
-      mint: rewardMint.publicKey,
-      extraRewardAccount: extraRewardSigner,
-      rewardVault: stateRewardVault,
-      userVault: u.rewardUserVault,
-      user: u.userAccount1,
-      state: stateSigner,
-      pool: poolSigner,
-      authority: u.publicKey,
-      ...defaultAccounts
-    }
-  });
-  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
-  return await cccc.getTransaction(hash)
-}
+    // campaign already ended => no more accrual
+    await setSchedule(new BN(1), [], new BN(0))
+    let stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.endTime.eq(new BN(1)))
 
-async function stake (user, amount, lock = 0) {
-  const tx = program.transaction.stake(amount, new BN(lock), {
-    accounts: {
-      mint: rewardMint.publicKey,
-      extraRewardAccount: extraRewardSigner,
-      poolVault: poolVault,
-      userVault: user.rewardUserVault,
-      user: user.userAccount1,
-      state: stateSigner,
-      pool: poolSigner,
-      authority: user.publicKey,
-      ...defaultAccounts
-    }
-  });
-  const hash = await user.provider.send(tx, [], { commitment: 'confirmed' });
-  return await cccc.getTransaction(hash)
-}
+    await harvestLP(userLP2)
+    await assertUserReward(userLP2, 0, false)
+    await sleep(2000)
+    await harvestLP(userLP2)
+    await assertUserReward(userLP2, userLP2.rewardAmount)
 
-async function unstakeLP (u, amount) {
-  const tx = program.transaction.unstake(amount, {
-    accounts: {
-      mint: lpMint.publicKey,
-      extraRewardAccount: extraRewardSigner,
-      poolVault: lpPoolVault,
-      userVault: u.lpUserVault,
-      user: u.lpUserAccount,
-      state: stateSigner,
-      pool: lpPoolSigner,
-      authority: u.publicKey,
-      ...defaultAccounts
-    }
-  });
-  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
-  return await cccc.getTransaction(hash)
-}
+    await setSchedule(new BN(0), [], new BN(0))
+  })
+  it('Pool rate mode', async function () {
+    const pools = await program.account.farmPoolAccount.all()
+    const changePoolRateMode = (rateMode, tokenPerSecond, remainingAccounts) => program.rpc.changePoolRateMode(rateMode, tokenPerSecond, {
+      accounts: {
+        pool: lpPoolSigner,
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    })
+    await assertError(changePoolRateMode(2, new BN(5), []), 'Invalid Rate Mode')
 
-async function harvestLP (u) {
-  const tx = program.transaction.harvest({
-    accounts: {
-      mint: lpMint.publicKey,
-      rewardVault: stateRewardVault,
-      extraRewardAccount: extraRewardSigner,
-      userVault: u.rewardUserVault,
-      user: u.lpUserAccount,
-      state: stateSigner,
-      pool: lpPoolSigner,
-      authority: u.publicKey,
-      ...defaultAccounts
-    }
-  });
-  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
-  return await cccc.getTransaction(hash)
-}
+    // the LP pool leaves total_point and earns its own rate
+    await changePoolRateMode(1, new BN(5), pools.map(p => ({
+      pubkey: p.publicKey,
+      isWritable: true,
+      isSigner: false
+    })))
+    let stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    let poolInfo = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    assert.ok(stateInfo.totalPoint.eq(new BN(0)))
+    assert.ok(stateInfo.totalPoolTokenPerSecond.eq(new BN(5)))
+    assert.ok(poolInfo.rateMode === 1)
 
-async function stakeLP (user, amount) {
-  const tx = program.transaction.stake(amount, new BN(0), {
-    accounts: {
-      mint: lpMint.publicKey,
-      poolVault: lpPoolVault,
-      extraRewardAccount: extraRewardSigner,
-      userVault: user.lpUserVault,
-      user: user.lpUserAccount,
-      state: stateSigner,
-      pool: lpPoolSigner,
-      authority: user.publicKey,
-      ...defaultAccounts
-    }
+    // changing its point no longer needs the other pools
+    await program.rpc.changePoolPoint(new BN(500), {
+      accounts: {
+        pool: lpPoolSigner,
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.totalPoint.eq(new BN(0)))
+
+    await harvestLP(userLP2)
+    await assertUserReward(userLP2, 0, false)
+    await sleep(2000)
+    await harvestLP(userLP2)
+    const before = userLP2.rewardAmount
+    await assertUserReward(userLP2, 0, false)
+    assert.ok(userLP2.rewardAmount.gt(before))
+
+    // back to the global points
+    await changePoolRateMode(0, new BN(0), pools.map(p => ({
+      pubkey: p.publicKey,
+      isWritable: true,
+      isSigner: false
+    })))
+    stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.totalPoolTokenPerSecond.eq(new BN(0)))
+    // the share is the point alone
+    assert.ok(stateInfo.totalPoint.eq(new BN(500)))
+  })
+  it('Reward streams', async function () {
+    const partnerMint = await createMint(provider, provider.wallet.publicKey)
+    const [stream, streamBump] = await PublicKey.findProgramAddress([
+      utf8.encode('stream'), lpPoolSigner.toBuffer(), Buffer.from([0])
+    ], program.programId)
+    const streamVault = await partnerMint.createAccount(stream)
+    const creatorPartnerVault = await partnerMint.createAccount(creatorKey)
+    await partnerMint.mintTo(creatorPartnerVault, provider.wallet.payer, [], getNumber(1000).toString())
+
+    await program.rpc.createRewardStream(streamBump, new BN(10), new BN(0), {
+      accounts: {
+        stream,
+        pool: lpPoolSigner,
+        mint: partnerMint.publicKey,
+        vault: streamVault,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    await program.rpc.fundRewardStream(new BN(1000000), {
+      accounts: {
+        stream,
+        authority: creatorKey,
+        streamVault,
+        userVault: creatorPartnerVault,
+        ...defaultAccounts
+      }
+    })
+    let poolInfo = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    assert.ok(poolInfo.streamCount === 1)
+
+    // every stream of the pool must be settled
+    await assertError(harvestLP(userLP2), 'Invalid Stream')
+
+    const userPartnerVault = await getOrCreateAssociatedSPL(userLP2.provider, partnerMint)
+    await harvestLP(userLP2, [stream])
+    await sleep(2000)
+    await harvestLP(userLP2, [stream, streamVault, userPartnerVault])
+    const paid = await getTokenAmount(userPartnerVault)
+    assert.ok(paid.gt(new BN(0)))
+
+    const streamInfo = await program.account.rewardStreamAccount.fetch(stream)
+    assert.ok(streamInfo.totalDistributed.eq(paid))
+    assert.ok(streamInfo.totalFunded.eq(new BN(1000000)))
+
+    // the staked principal of an unbonding pool can't skip the cooldown unless the pool is unlocked
+    await assertError(emergencyWithdrawLP(userLP1, [stream]), 'Unbonding required')
+    const changeEmergencyUnlock = (emergencyUnlocked) => program.rpc.changePoolEmergencyUnlock(emergencyUnlocked, {
+      accounts: {
+        pool: lpPoolSigner,
+        authority: creatorKey,
+      }
+    })
+    await changeEmergencyUnlock(true)
+    // the forfeited stream reward goes back to the stream budget
+    await emergencyWithdrawLP(userLP1, [stream])
+    await changeEmergencyUnlock(false)
+    const streamAfter = await program.account.rewardStreamAccount.fetch(stream)
+    const position = await program.account.farmPoolPositionAccount.fetch(userLP1.lpPositionAccounts[0])
+    assert.ok(streamAfter.rewardCommitted.lt(streamInfo.rewardCommitted))
+    assert.ok(streamAfter.totalDistributed.eq(streamInfo.totalDistributed))
+    assert.ok(position.amount.eq(new BN(0)))
+    assert.ok(position.unbondingAmount.eq(new BN(0)))
+  })
+  it('Second state in the same program', async function () {
+    const stateId = new BN(1)
+    const tenantMint = await createMint(provider, provider.wallet.publicKey)
+    const [tenantState, tenantStateBump] = await PublicKey.findProgramAddress([
+      utf8.encode('state'), stateId.toArrayLike(Buffer, 'le', 8)
+    ], program.programId)
+    const [tenantRegistry, tenantRegistryBump] = await PublicKey.findProgramAddress([
+      utf8.encode('registry'), tenantState.toBuffer()
+    ], program.programId)
+    const [tenantExtra, tenantExtraBump] = await PublicKey.findProgramAddress([
+      utf8.encode('extra'), stateId.toArrayLike(Buffer, 'le', 8)
+    ], program.programId)
+    // same staking mint as the first state's pool 0, namespaced by the state
+    const [tenantPool, tenantPoolBump] = await PublicKey.findProgramAddress([
+      rewardMint.publicKey.toBuffer(), tenantState.toBuffer()
+    ], program.programId)
+    const tenantRewardVault = await tenantMint.createAccount(tenantState)
+    const tenantPoolVault = await rewardMint.createAccount(tenantPool)
+
+    const [tenantMigration] = await getMigrationAddress(tenantState)
+    await program.rpc.createState(tenantStateBump, stateId, new BN(5), {
+      accounts: {
+        state: tenantState,
+        rewardMint: tenantMint.publicKey,
+        rewardVault: tenantRewardVault,
+        migration: tenantMigration,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    await program.rpc.createPoolRegistry(tenantRegistryBump, {
+      accounts: {
+        registry: tenantRegistry,
+        state: tenantState,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    await program.rpc.createExtraRewardConfigs(tenantExtraBump, [
+      { duration: new BN(0), extraPercentage: getNumber(0) },
+    ], {
+      accounts: {
+        extraRewardAccount: tenantExtra,
+        state: tenantState,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+    })
+    await program.rpc.createPool(tenantPoolBump, new BN(0), new BN(0), {
+      accounts: {
+        pool: tenantPool,
+        state: tenantState,
+        registry: tenantRegistry,
+        mint: rewardMint.publicKey,
+        vault: tenantPoolVault,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    const stateInfo = await program.account.stateAccount.fetch(tenantState)
+    const poolInfo = await program.account.farmPoolAccount.fetch(tenantPool)
+    assert.ok(stateInfo.stateId.eq(stateId))
+    assert.ok(stateInfo.rewardMint.equals(tenantMint.publicKey))
+    assert.ok(poolInfo.state.equals(tenantState))
+
+    // pools of the first state can't be updated with this one
+    await assertError(program.rpc.changeTokensPerSecond(new BN(5), {
+      accounts: {
+        state: tenantState,
+        registry: tenantRegistry,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts: [{ pubkey: poolSigner, isWritable: true, isSigner: false }]
+    }), 'Invalid State')
+  })
+  it('Two-step authority transfer', async function () {
+    const newAuthority = user3
+    const proposeStateAuthority = (pendingAuthority) => program.rpc.proposeStateAuthority(pendingAuthority, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+    const acceptStateAuthority = (u) => program.rpc.acceptStateAuthority({
+      accounts: {
+        state: stateSigner,
+        pendingAuthority: u.publicKey,
+      },
+      signers: [u.user]
+    })
+
+    await proposeStateAuthority(newAuthority.publicKey)
+    let stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.pendingAuthority.equals(newAuthority.publicKey))
+    await program.rpc.cancelStateAuthority({
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+    stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.pendingAuthority.equals(PublicKey.default))
+    await assertError(acceptStateAuthority(newAuthority), 'A raw constraint was violated')
+
+    await proposeStateAuthority(newAuthority.publicKey)
+    await assertError(acceptStateAuthority(user4), 'A raw constraint was violated')
+    await acceptStateAuthority(newAuthority)
+    stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.authority.equals(newAuthority.publicKey))
+    assert.ok(stateInfo.pendingAuthority.equals(PublicKey.default))
+
+    // hand it back
+    await program.rpc.proposeStateAuthority(creatorKey, {
+      accounts: {
+        state: stateSigner,
+        authority: newAuthority.publicKey,
+      },
+      signers: [newAuthority.user]
+    })
+    await program.rpc.acceptStateAuthority({
+      accounts: {
+        state: stateSigner,
+        pendingAuthority: creatorKey,
+      }
+    })
+    stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.authority.equals(creatorKey))
+
+    await program.rpc.proposePoolAuthority(newAuthority.publicKey, {
+      accounts: {
+        pool: lpPoolSigner,
+        authority: creatorKey,
+      }
+    })
+    await program.rpc.acceptPoolAuthority({
+      accounts: {
+        pool: lpPoolSigner,
+        pendingAuthority: newAuthority.publicKey,
+      },
+      signers: [newAuthority.user]
+    })
+    const poolInfo = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    assert.ok(poolInfo.authority.equals(newAuthority.publicKey))
+
+    await program.rpc.proposeExtraRewardAuthority(newAuthority.publicKey, {
+      accounts: {
+        extraRewardAccount: lpExtraRewardSigner,
+        authority: creatorKey,
+      }
+    })
+    await program.rpc.acceptExtraRewardAuthority({
+      accounts: {
+        extraRewardAccount: lpExtraRewardSigner,
+        pendingAuthority: newAuthority.publicKey,
+      },
+      signers: [newAuthority.user]
+    })
+    const extraInfo = await program.account.extraRewardsAccount.fetch(lpExtraRewardSigner)
+    assert.ok(extraInfo.authority.equals(newAuthority.publicKey))
+  })
+  it('Admin roles', async function () {
+    const [funder, operator] = [user4, user2]
+    const { pools } = await program.account.poolRegistryAccount.fetch(registrySigner)
+    await program.rpc.setRoles(funder.publicKey, operator.publicKey, PublicKey.default, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+    const changeTokensPerSecond = (u, tokenPerSecond) => program.rpc.changeTokensPerSecond(tokenPerSecond, {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: u.publicKey,
+        ...defaultAccounts
+      },
+      remainingAccounts: pools.map(pubkey => ({
+        pubkey,
+        isWritable: true,
+        isSigner: false
+      })),
+      signers: [u.user]
+    })
+    const fund = (u, amount) => program.rpc.fundRewardToken(amount, {
+      accounts: {
+        state: stateSigner,
+        rewardVault: stateRewardVault,
+        userVault: u.rewardUserVault,
+        authority: u.publicKey,
+        ...defaultAccounts
+      },
+      signers: [u.user]
+    })
+
+    let stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.operator.equals(operator.publicKey))
+    await changeTokensPerSecond(operator, stateInfo.tokenPerSecond)
+    await assertError(changeTokensPerSecond(funder, stateInfo.tokenPerSecond), 'A raw constraint was violated')
+
+    await fund(funder, new BN(1))
+    await assertError(fund(operator, new BN(1)), 'A raw constraint was violated')
+
+    // the terms stakers rely on stay with the authority
+    await assertError(program.rpc.changePoolKeeperTip(new BN(0), {
+      accounts: {
+        pool: poolSigner,
+        authority: operator.publicKey,
+      },
+      signers: [operator.user]
+    }), 'A has_one constraint was violated')
+    await assertError(program.rpc.changePoolEmergencyUnlock(true, {
+      accounts: {
+        pool: poolSigner,
+        authority: operator.publicKey,
+      },
+      signers: [operator.user]
+    }), 'A has_one constraint was violated')
+    await assertError(program.rpc.setEarlyUnstakePenalty({
+      enabled: false,
+      principalPercentage: new BN(0),
+      extraRewardPercentage: new BN(0),
+      linearDecay: false,
+      destination: 0,
+      treasury: operator.publicKey,
+    }, {
+      accounts: {
+        extraRewardAccount: extraRewardSigner,
+        authority: operator.publicKey,
+      },
+      signers: [operator.user]
+    }), 'A has_one constraint was violated')
+    const poolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
+    assert.ok(poolInfo.emergencyUnlocked === 0)
+    const totalFunded = stateInfo.totalFunded
+    stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.totalFunded.eq(totalFunded.add(new BN(1))))
+
+    await program.rpc.setRoles(PublicKey.default, PublicKey.default, PublicKey.default, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+  })
+  it('Pause', async function () {
+    const pauser = user1
+    await program.rpc.setRoles(PublicKey.default, PublicKey.default, pauser.publicKey, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+    const setPaused = (u, paused, unstakePaused) => program.rpc.setPaused(paused, unstakePaused, {
+      accounts: {
+        state: stateSigner,
+        authority: u.publicKey,
+      },
+      signers: [u.user]
+    })
+    const setPoolPaused = (u, paused, unstakePaused) => program.rpc.setPoolPaused(paused, unstakePaused, {
+      accounts: {
+        state: stateSigner,
+        pool: poolSigner,
+        authority: u.publicKey,
+      },
+      signers: [u.user]
+    })
+
+    await setPaused(pauser, true, false)
+    let stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.paused === 1)
+    await assertError(stake(user4, new BN(100), 2), 'Paused')
+    await assertError(harvest(user4), 'Paused')
+    await assertError(setPaused(pauser, false, false), 'Not authorized to resume')
+    await assertError(setPaused(user2, true, true), 'A raw constraint was violated')
+    await program.rpc.setPaused(false, false, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+
+    await setPoolPaused(pauser, false, true)
+    const poolInfo = await program.account.farmPoolAccount.fetch(poolSigner)
+    assert.ok(poolInfo.unstakePaused === 1)
+    await createPosition(user4)
+    await stake(user4, new BN(100), 0, 3)
+    await assertError(unstake(user4, new BN(100), 3), 'Unstake paused')
+    await program.rpc.setPoolPaused(false, false, {
+      accounts: {
+        state: stateSigner,
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    })
+    await unstake(user4, new BN(100), 3)
+
+    await program.rpc.setRoles(PublicKey.default, PublicKey.default, PublicKey.default, {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+  })
+  it('Emergency withdraw', async function () {
+    const changeEmergencyUnlock = (emergencyUnlocked) => program.rpc.changePoolEmergencyUnlock(emergencyUnlocked, {
+      accounts: {
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    })
+    const setPoolPaused = (paused, unstakePaused) => program.rpc.setPoolPaused(paused, unstakePaused, {
+      accounts: {
+        state: stateSigner,
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    })
+    await createPosition(user4)
+    const index = user4.positionAccounts.length - 1
+    await stake(user4, new BN(100), 1000, index)
+    await setPoolPaused(true, true)
+
+    await assertError(emergencyWithdraw(user4, index), 'Under locked')
+    await changeEmergencyUnlock(true)
+
+    const poolBefore = await program.account.farmPoolAccount.fetch(poolSigner)
+    const vaultBefore = await getTokenAmount(user4.rewardUserVault)
+    await emergencyWithdraw(user4, index)
+    const vaultAfter = await getTokenAmount(user4.rewardUserVault)
+    const poolAfter = await program.account.farmPoolAccount.fetch(poolSigner)
+    const position = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[index])
+    assert.ok(vaultAfter.sub(vaultBefore).eq(new BN(100)))
+    assert.ok(poolBefore.amount.sub(poolAfter.amount).eq(new BN(100)))
+    assert.ok(position.amount.eq(new BN(0)))
+    assert.ok(position.rewardAmount.eq(new BN(0)))
+    assert.ok(position.extraReward.eq(new BN(0)))
+    await assertError(emergencyWithdraw(user4, index), 'Invalid Amount')
+
+    await changeEmergencyUnlock(false)
+    await setPoolPaused(false, false)
+  })
+  it('Timelocked admin changes', async function () {
+    const [queue, queueBump] = await PublicKey.findProgramAddress([
+      utf8.encode('queue'), stateSigner.toBuffer()
+    ], program.programId)
+    await program.rpc.createAdminQueue(queueBump, {
+      accounts: {
+        queue,
+        state: stateSigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    await program.rpc.setTimelockDelay(new BN(1), {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    })
+    const { pools } = await program.account.poolRegistryAccount.fetch(registrySigner)
+    const remainingAccounts = pools.map(pubkey => ({
+      pubkey,
+      isWritable: true,
+      isSigner: false
+    }))
+    const getNow = async () => await provider.connection.getBlockTime(await provider.connection.getSlot())
+    const queueChange = (kind, target, value, eta) => program.rpc.queueChange(kind, target, value, [], new BN(eta), {
+      accounts: {
+        state: stateSigner,
+        queue,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    const executeStateChange = (id) => program.rpc.executeStateChange(id, {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        queue,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    })
+
+    await assertError(program.rpc.changeTokensPerSecond(new BN(50), {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    }), 'Timelock required')
+    await assertError(program.rpc.setEmissionSchedule(new BN(0), [], new BN(0), {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    }), 'Timelock required')
+    await assertError(program.rpc.changePoolRateMode(0, new BN(0), {
+      accounts: {
+        pool: poolSigner,
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    }), 'Timelock required')
+    await assertError(program.rpc.setTimelockDelay(new BN(31 * 86400), {
+      accounts: {
+        state: stateSigner,
+        authority: creatorKey,
+      }
+    }), 'Timelock too long')
+
+    let now = await getNow()
+    await assertError(queueChange(0, PublicKey.default, new BN(50), now - 10), 'Timelock too short')
+    await assertError(queueChange(3, PublicKey.default, new BN(31 * 86400), now + 3), 'Timelock too long')
+    await queueChange(0, PublicKey.default, new BN(50), now + 3)
+    let queueInfo = await program.account.adminQueueAccount.fetch(queue)
+    const rateChangeId = queueInfo.changes[0].id
+    await assertError(executeStateChange(rateChangeId), 'Timelock not ready')
+
+    await queueChange(1, poolSigner, new BN(1), now + 3)
+    queueInfo = await program.account.adminQueueAccount.fetch(queue)
+    await program.rpc.cancelChange(queueInfo.changes[1].id, {
+      accounts: {
+        state: stateSigner,
+        queue,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    queueInfo = await program.account.adminQueueAccount.fetch(queue)
+    assert.ok(queueInfo.changes.length === 1)
+
+    await sleep(4000)
+    await executeStateChange(rateChangeId)
+    const stateInfo = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(stateInfo.tokenPerSecond.eq(new BN(50)))
+
+    // under a timelock a new pool starts without a share, its point is queued
+    const timelockPoolId = new BN(2)
+    const [timelockPool, timelockPoolBump] = await PublicKey.findProgramAddress([
+      rewardMint.publicKey.toBuffer(), timelockPoolId.toArrayLike(Buffer, 'le', 8)
+    ], program.programId)
+    await assertError(program.rpc.createPool(timelockPoolBump, timelockPoolId, new BN(100), {
+      accounts: {
+        pool: timelockPool,
+        state: stateSigner,
+        registry: registrySigner,
+        mint: rewardMint.publicKey,
+        vault: await rewardMint.createAccount(timelockPool),
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    }), 'Timelock required')
+
+    // the schedule and the pool rate mode have their own queued forms
+    now = await getNow()
+    await assertError(queueChange(4, PublicKey.default, new BN(0), now + 3), 'Invalid Queued Change')
+    const queueAccounts = {
+      state: stateSigner,
+      queue,
+      authority: creatorKey,
+      ...defaultAccounts
+    }
+    const queuePoolRateMode = (rateMode, tokenPerSecond, eta) => program.rpc.queuePoolRateMode(lpPoolSigner, rateMode, new BN(tokenPerSecond), new BN(eta), {
+      accounts: queueAccounts
+    })
+    const executePoolChange = (id) => program.rpc.executePoolChange(id, {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        queue,
+        pool: lpPoolSigner,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    })
+    await program.rpc.queueEmissionSchedule(new BN(0), [], new BN(0), new BN(now + 3), {
+      accounts: queueAccounts
+    })
+    await queuePoolRateMode(1, 5, now + 3)
+    queueInfo = await program.account.adminQueueAccount.fetch(queue)
+    const [scheduleChange, rateModeChange] = queueInfo.changes
+    await sleep(4000)
+    await executeStateChange(scheduleChange.id)
+    await executePoolChange(rateModeChange.id)
+    let lpPoolInfo = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    assert.ok(lpPoolInfo.rateMode === 1)
+    assert.ok(lpPoolInfo.tokenPerSecond.eq(new BN(5)))
+
+    now = await getNow()
+    await queuePoolRateMode(0, 0, now + 3)
+    queueInfo = await program.account.adminQueueAccount.fetch(queue)
+    await sleep(4000)
+    await executePoolChange(queueInfo.changes[0].id)
+    lpPoolInfo = await program.account.farmPoolAccount.fetch(lpPoolSigner)
+    assert.ok(lpPoolInfo.rateMode === 0)
+
+    now = await getNow()
+    await queueChange(3, PublicKey.default, new BN(0), now + 3)
+    queueInfo = await program.account.adminQueueAccount.fetch(queue)
+    await sleep(4000)
+    await executeStateChange(queueInfo.changes[0].id)
+    const { timelockDelay } = await program.account.stateAccount.fetch(stateSigner)
+    assert.ok(timelockDelay.eq(new BN(0)))
+  })
+  it('Parameter caps', async function () {
+    const setCaps = (maxTokenPerSecond, maxExtraPercentage, maxRateChangePerDay, maxLockDuration) => program.rpc.setCaps(
+      new BN(maxTokenPerSecond), maxExtraPercentage, new BN(maxRateChangePerDay), new BN(maxLockDuration), {
+        accounts: {
+          state: stateSigner,
+          authority: creatorKey,
+        }
+      })
+    const { pools } = await program.account.poolRegistryAccount.fetch(registrySigner)
+    const remainingAccounts = pools.map(pubkey => ({
+      pubkey,
+      isWritable: true,
+      isSigner: false
+    }))
+    const changeTokensPerSecond = (tokenPerSecond) => program.rpc.changeTokensPerSecond(new BN(tokenPerSecond), {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    })
+    const setExtraRewardConfigs = (configs) => program.rpc.setExtraRewardConfigs(configs, {
+      accounts: {
+        extraRewardAccount: extraRewardSigner,
+        state: stateSigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+    })
+    const { configs } = await program.account.extraRewardsAccount.fetch(extraRewardSigner)
+
+    await setCaps(100, getNumber(100), 0, 1000)
+    await assertError(changeTokensPerSecond(101), 'Rate above max')
+    await changeTokensPerSecond(100)
+    const setEmissionSchedule = (endTime, steps, halvingInterval) => program.rpc.setEmissionSchedule(new BN(endTime), steps, new BN(halvingInterval), {
+      accounts: {
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    })
+    await assertError(setEmissionSchedule(0, [
+      { startTime: new BN(1), tokenPerSecond: new BN(101) },
+    ], 0), 'Rate above max')
+    await assertError(program.rpc.changePoolRateMode(1, new BN(101), {
+      accounts: {
+        pool: lpPoolSigner,
+        state: stateSigner,
+        registry: registrySigner,
+        authority: creatorKey,
+        ...defaultAccounts
+      },
+      remainingAccounts
+    }), 'Rate above max')
+    await assertError(setExtraRewardConfigs([
+      { duration: new BN(1), extraPercentage: getNumber(101) },
+    ]), 'Extra percentage above max')
+    await assertError(setExtraRewardConfigs([
+      { duration: new BN(1001), extraPercentage: getNumber(10) },
+    ]), 'Lock duration above max')
+    await setExtraRewardConfigs(configs)
+
+    // the last change was just made, so the daily allowance is close to zero
+    await setCaps(0, new BN(0), 86400, 0)
+    await assertError(changeTokensPerSecond(40), 'Rate change above daily max')
+    // the halvings and the end of a schedule are rate changes as well
+    await assertError(setEmissionSchedule(0, [], 10), 'Rate change above daily max')
+    await assertError(setEmissionSchedule(Math.floor(Date.now() / 1000) + 30, [], 0), 'Rate change above daily max')
+
+    await setCaps(0, new BN(0), 0, 0)
+    await changeTokensPerSecond(40)
+  })
+  it('Compound', async function () {
+    await createPosition(user4)
+    const index = user4.positionAccounts.length - 1
+    await stake(user4, new BN(100), 1000, index)
+    const accounts = {
+      user: user4.userAccount1,
+      position: user4.positionAccounts[index],
+      state: stateSigner,
+      extraRewardAccount: extraRewardSigner,
+      pool: poolSigner,
+      rewardVault: stateRewardVault,
+      poolVault: poolVault,
+      ...defaultAccounts
+    }
+    const keeperVault = await rewardMint.createAccount(creatorKey)
+    const crankCompound = () => program.rpc.crankCompound({
+      accounts: {
+        ...accounts,
+        keeper: creatorKey,
+        keeperVault
+      }
+    })
+
+    await sleep(2000)
+    const before = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[index])
+    await program.rpc.compound({
+      accounts: {
+        ...accounts,
+        authority: user4.publicKey
+      },
+      signers: [user4.user]
+    })
+    let position = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[index])
+    assert.ok(position.amount.gt(before.amount))
+    assert.ok(position.lastStakeTime.eq(before.lastStakeTime))
+    assert.ok(position.lockDuration.eq(before.lockDuration))
+
+    await assertError(crankCompound(), 'Auto compound disabled')
+    await program.rpc.setAutoCompound(true, {
+      accounts: {
+        user: user4.userAccount1,
+        authority: user4.publicKey
+      },
+      signers: [user4.user]
+    })
+    await sleep(2000)
+    const amount = position.amount
+    await crankCompound()
+    position = await program.account.farmPoolPositionAccount.fetch(user4.positionAccounts[index])
+    assert.ok(position.amount.gt(amount))
+  })
+  it('Crank harvest', async function () {
+    const index = user4.positionAccounts.length - 1
+    const keeperVault = await rewardMint.createAccount(creatorKey)
+    const crankHarvest = () => program.rpc.crankHarvest({
+      accounts: {
+        user: user4.userAccount1,
+        position: user4.positionAccounts[index],
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        keeper: creatorKey,
+        keeperVault,
+        rewardVault: stateRewardVault,
+        userVault: user4.rewardUserVault,
+        ...defaultAccounts
+      }
+    })
+    await assertError(crankHarvest(), 'Auto harvest disabled')
+    await program.rpc.setAutoHarvest(true, {
+      accounts: {
+        user: user4.userAccount1,
+        authority: user4.publicKey
+      },
+      signers: [user4.user]
+    })
+    const changeKeeperTip = (percentage) => program.rpc.changePoolKeeperTip(percentage, {
+      accounts: {
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    })
+    await assertError(changeKeeperTip(getNumber(11)), 'Invalid Keeper Tip')
+    await changeKeeperTip(getNumber(10))
+
+    await sleep(2000)
+    const userBefore = await getTokenAmount(user4.rewardUserVault)
+    await crankHarvest()
+    const userAfter = await getTokenAmount(user4.rewardUserVault)
+    const keeperAmount = await getTokenAmount(keeperVault)
+    const received = userAfter.sub(userBefore)
+    assert.ok(received.gt(new BN(0)))
+    // the keeper gets 10% of the harvested rewards
+    assert.ok(keeperAmount.eq(received.add(keeperAmount).div(new BN(10))))
+
+    await changeKeeperTip(new BN(0))
+  })
+  it('Harvest to a recipient by a delegate', async function () {
+    const index = user4.positionAccounts.length - 1
+    const delegate = user2
+    const harvestTo = (claimer, recipientVault) => program.rpc.harvestTo({
+      accounts: {
+        user: user4.userAccount1,
+        position: user4.positionAccounts[index],
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        claimer: claimer.publicKey,
+        rewardVault: stateRewardVault,
+        recipientVault,
+        ...defaultAccounts
+      },
+      signers: [claimer.user]
+    })
+    await assertError(harvestTo(delegate, delegate.rewardUserVault), 'A raw constraint was violated')
+    await program.rpc.setDelegate(delegate.publicKey, {
+      accounts: {
+        user: user4.userAccount1,
+        authority: user4.publicKey
+      },
+      signers: [user4.user]
+    })
+    const userInfo = await program.account.farmPoolUserAccount.fetch(user4.userAccount1)
+    assert.ok(userInfo.delegate.equals(delegate.publicKey))
+
+    await sleep(2000)
+    const before = await getTokenAmount(delegate.rewardUserVault)
+    await harvestTo(delegate, delegate.rewardUserVault)
+    const after = await getTokenAmount(delegate.rewardUserVault)
+    assert.ok(after.gt(before))
+    await assertError(harvestTo(user3, user3.rewardUserVault), 'A raw constraint was violated')
+  })
+  it('Vesting', async function () {
+    const index = user4.positionAccounts.length - 1
+    const changePoolVesting = (vestingMode, vestingPeriod, earlyClaimPenalty) => program.rpc.changePoolVesting(vestingMode, new BN(vestingPeriod), earlyClaimPenalty, {
+      accounts: {
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    })
+    const [vesting, vestingBump] = await PublicKey.findProgramAddress([
+      utf8.encode('vesting'), user4.userAccount1.toBuffer()
+    ], program.programId)
+    await program.rpc.createVesting(vestingBump, {
+      accounts: {
+        vesting,
+        user: user4.userAccount1,
+        pool: poolSigner,
+        authority: user4.publicKey,
+        ...defaultAccounts
+      },
+      signers: [user4.user]
+    })
+
+    await assertError(changePoolVesting(1, 0, new BN(0)), 'Invalid Vesting')
+    await changePoolVesting(1, 100, getNumber(50))
+    await assertError(harvest(user4, index), 'Vesting required')
+
+    await sleep(2000)
+    const vaultBefore = await getTokenAmount(user4.rewardUserVault)
+    await program.rpc.harvestVesting({
+      accounts: {
+        user: user4.userAccount1,
+        position: user4.positionAccounts[index],
+        vesting,
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        authority: user4.publicKey,
+        rewardVault: stateRewardVault,
+        userVault: user4.rewardUserVault,
+        ...defaultAccounts
+      },
+      signers: [user4.user]
+    })
+    let vaultAfter = await getTokenAmount(user4.rewardUserVault)
+    let vestingInfo = await program.account.vestingAccount.fetch(vesting)
+    assert.ok(vaultAfter.eq(vaultBefore))
+    assert.ok(vestingInfo.amount.gt(new BN(0)))
+
+    // a second deposit doesn't restart the whole period
+    await sleep(2000)
+    await program.rpc.harvestVesting({
+      accounts: {
+        user: user4.userAccount1,
+        position: user4.positionAccounts[index],
+        vesting,
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        authority: user4.publicKey,
+        rewardVault: stateRewardVault,
+        userVault: user4.rewardUserVault,
+        ...defaultAccounts
+      },
+      signers: [user4.user]
+    })
+    const firstEndTime = vestingInfo.endTime
+    vestingInfo = await program.account.vestingAccount.fetch(vesting)
+    assert.ok(vestingInfo.endTime.gte(firstEndTime))
+    assert.ok(vestingInfo.endTime.sub(vestingInfo.startTime).lt(new BN(100)))
+
+    // an early claim forfeits half of the part not vested yet
+    await program.rpc.claimVested(true, {
+      accounts: {
+        vesting,
+        state: stateSigner,
+        pool: poolSigner,
+        authority: user4.publicKey,
+        rewardVault: stateRewardVault,
+        userVault: user4.rewardUserVault,
+        ...defaultAccounts
+      },
+      signers: [user4.user]
+    })
+    vaultAfter = await getTokenAmount(user4.rewardUserVault)
+    const received = vaultAfter.sub(vaultBefore)
+    assert.ok(received.gte(vestingInfo.amount.div(new BN(2))))
+    assert.ok(received.lt(vestingInfo.amount))
+    vestingInfo = await program.account.vestingAccount.fetch(vesting)
+    assert.ok(vestingInfo.amount.eq(new BN(0)))
+
+    await changePoolVesting(0, 0, new BN(0))
+  })
+  it('Stake for a beneficiary', async function () {
+    const funder = user1
+    const beneficiary = Keypair.generate()
+    const [user, userBump] = await PublicKey.findProgramAddress([
+      poolSigner.toBuffer(), beneficiary.publicKey.toBuffer()
+    ], program.programId)
+    await program.rpc.createUserFor(userBump, {
+      accounts: {
+        user,
+        state: stateSigner,
+        pool: poolSigner,
+        beneficiary: beneficiary.publicKey,
+        funder: funder.publicKey,
+        ...defaultAccounts
+      },
+      signers: [funder.user]
+    })
+    const [position, positionBump] = await getPositionAddress(user, new BN(0))
+    await program.rpc.stakeFor(positionBump, new BN(100), new BN(1000), {
+      accounts: {
+        position,
+        user,
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        funder: funder.publicKey,
+        poolVault: poolVault,
+        funderVault: funder.rewardUserVault,
+        ...defaultAccounts
+      },
+      signers: [funder.user]
+    })
+    const positionInfo = await program.account.farmPoolPositionAccount.fetch(position)
+    assert.ok(positionInfo.authority.equals(beneficiary.publicKey))
+    assert.ok(positionInfo.amount.eq(new BN(100)))
+    assert.ok(positionInfo.lockDuration.eq(new BN(1000)))
+    assert.ok(positionInfo.funderLocked === 1)
+    const userInfo = await program.account.farmPoolUserAccount.fetch(user)
+    assert.ok(userInfo.amount.eq(new BN(100)))
+    assert.ok(userInfo.positionCount.eq(new BN(1)))
+
+    // the beneficiary can't leave the funder lock early, even in an unlocked pool
+    const beneficiaryAccounts = {
+      mint: rewardMint.publicKey,
+      extraRewardAccount: extraRewardSigner,
+      poolVault: poolVault,
+      userVault: await rewardMint.createAccount(beneficiary.publicKey),
+      user,
+      position,
+      state: stateSigner,
+      pool: poolSigner,
+      authority: beneficiary.publicKey,
+      ...defaultAccounts
+    }
+    await assertError(program.rpc.earlyUnstake(new BN(100), {
+      accounts: {
+        ...beneficiaryAccounts,
+        treasuryVault: await rewardMint.createAccount(creatorKey)
+      },
+      signers: [beneficiary]
+    }), 'Under locked')
+    const changeEmergencyUnlock = (emergencyUnlocked) => program.rpc.changePoolEmergencyUnlock(emergencyUnlocked, {
+      accounts: {
+        pool: poolSigner,
+        authority: creatorKey,
+      }
+    })
+    await changeEmergencyUnlock(true)
+    await assertError(program.rpc.emergencyWithdraw({
+      accounts: beneficiaryAccounts,
+      signers: [beneficiary]
+    }), 'Under locked')
+    await changeEmergencyUnlock(false)
+    const lockedInfo = await program.account.farmPoolPositionAccount.fetch(position)
+    assert.ok(lockedInfo.amount.eq(new BN(100)))
+
+    // once the funder lock has passed, a partial unstake relocks as the beneficiary's own lock
+    const [shortPosition, shortPositionBump] = await getPositionAddress(user, new BN(1))
+    await program.rpc.stakeFor(shortPositionBump, new BN(100), new BN(2), {
+      accounts: {
+        position: shortPosition,
+        user,
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        funder: funder.publicKey,
+        poolVault: poolVault,
+        funderVault: funder.rewardUserVault,
+        ...defaultAccounts
+      },
+      signers: [funder.user]
+    })
+    await sleep(3000)
+    const shortAccounts = { ...beneficiaryAccounts, position: shortPosition }
+    await program.rpc.unstake(new BN(50), {
+      accounts: shortAccounts,
+      signers: [beneficiary]
+    })
+    let shortInfo = await program.account.farmPoolPositionAccount.fetch(shortPosition)
+    assert.ok(shortInfo.funderLocked === 0)
+    assert.ok(shortInfo.amount.eq(new BN(50)))
+    await changeEmergencyUnlock(true)
+    await program.rpc.emergencyWithdraw({
+      accounts: shortAccounts,
+      signers: [beneficiary]
+    })
+    await changeEmergencyUnlock(false)
+    shortInfo = await program.account.farmPoolPositionAccount.fetch(shortPosition)
+    assert.ok(shortInfo.amount.eq(new BN(0)))
+  })
+  it('Migrate a legacy user', async function () {
+    // accounts created with positions have nothing to migrate
+    const owner = Keypair.generate()
+    await connection.confirmTransaction(await connection.requestAirdrop(owner.publicKey, web3.LAMPORTS_PER_SOL))
+    const [user, userBump] = await PublicKey.findProgramAddress([
+      poolSigner.toBuffer(), owner.publicKey.toBuffer()
+    ], program.programId)
+    await program.rpc.createUser(userBump, {
+      accounts: {
+        user,
+        state: stateSigner,
+        pool: poolSigner,
+        authority: owner.publicKey,
+        ...defaultAccounts
+      },
+      signers: [owner]
+    })
+    const [position, positionBump] = await getPositionAddress(user, new BN(0))
+    await assertError(program.rpc.migrateUser(positionBump, {
+      accounts: {
+        position,
+        user,
+        state: stateSigner,
+        extraRewardAccount: extraRewardSigner,
+        pool: poolSigner,
+        authority: owner.publicKey,
+        ...defaultAccounts
+      },
+      signers: [owner]
+    }), 'Nothing to migrate')
+    const userInfo = await program.account.farmPoolUserAccount.fetch(user)
+    assert.ok(userInfo.positionCount.eq(new BN(0)))
+  })
+  it('Migrate legacy accounts', async function () {
+    const stateId = new BN(7)
+    const legacyMint = await createMint(provider, provider.wallet.publicKey)
+    const stateSeeds = [Buffer.from('state'), stateId.toArrayLike(Buffer, 'le', 8)]
+    const [legacyState, legacyStateBump] = await PublicKey.findProgramAddress(stateSeeds, program.programId)
+    const poolSeeds = [legacyMint.publicKey.toBuffer(), legacyState.toBuffer()]
+    const [legacyPool, legacyPoolBump] = await PublicKey.findProgramAddress(poolSeeds, program.programId)
+    const extraSeeds = [Buffer.from('extra'), stateId.toArrayLike(Buffer, 'le', 8)]
+    const [legacyExtra, legacyExtraBump] = await PublicKey.findProgramAddress(extraSeeds, program.programId)
+    const [legacyRegistry, legacyRegistryBump] = await PublicKey.findProgramAddress([
+      utf8.encode('registry'), legacyState.toBuffer()
+    ], program.programId)
+    const legacyRewardVault = await legacyMint.createAccount(legacyState)
+    const legacyPoolVault = await legacyMint.createAccount(legacyPool)
+    await legacyMint.mintTo(legacyRewardVault, creatorKey, [provider.wallet], new BN(1000).toString())
+    await legacyMint.mintTo(legacyPoolVault, creatorKey, [provider.wallet], new BN(100).toString())
+    const now = Math.floor(Date.now() / 1000)
+
+    // the first version layouts, with their sizes at the time
+    const stateData = Buffer.concat([
+      getDiscriminator('StateAccount'),
+      creatorKey.toBuffer(),
+      legacyMint.publicKey.toBuffer(),
+      legacyRewardVault.toBuffer(),
+      Buffer.from([legacyStateBump]),
+      new BN(1000).toArrayLike(Buffer, 'le', 8), // total_point
+      new BN(now).toArrayLike(Buffer, 'le', 8), // start_time
+      new BN(10).toArrayLike(Buffer, 'le', 8), // token_per_second
+    ])
+    const poolData = Buffer.concat([
+      getDiscriminator('FarmPoolAccount'),
+      Buffer.from([legacyPoolBump]),
+      creatorKey.toBuffer(),
+      new BN(100).toArrayLike(Buffer, 'le', 8), // amount
+      legacyMint.publicKey.toBuffer(),
+      legacyPoolVault.toBuffer(),
+      new BN(1000).toArrayLike(Buffer, 'le', 8), // point
+      new BN(now).toArrayLike(Buffer, 'le', 8), // last_reward_time
+      new BN(0).toArrayLike(Buffer, 'le', 16), // acc_reward_per_share
+      new BN(1).toArrayLike(Buffer, 'le', 8), // amount_multipler
+      new BN(1).toArrayLike(Buffer, 'le', 8), // total_user
+    ])
+    const extraData = Buffer.alloc(8 + 197)
+    Buffer.concat([
+      getDiscriminator('ExtraRewardsAccount'),
+      Buffer.from([legacyExtraBump]),
+      creatorKey.toBuffer(),
+      new BN(1).toArrayLike(Buffer, 'le', 4), // configs
+      new BN(0).toArrayLike(Buffer, 'le', 8),
+      getNumber(0).toArrayLike(Buffer, 'le', 8),
+    ]).copy(extraData)
+    assert.ok(stateData.length === 8 + 121)
+    assert.ok(poolData.length === 8 + 153)
+    try {
+      await writeLegacyFixture(stateSeeds, stateData)
+    } catch (error) {
+      // only a build with the legacy-fixtures feature writes them
+      if (error.msg === 'Legacy fixtures disabled') return this.skip()
+      throw error
+    }
+    await writeLegacyFixture(poolSeeds, poolData)
+    await writeLegacyFixture(extraSeeds, extraData)
+
+    // a baseline sized state can't be loaded before it is migrated
+    let loaded = true
+    try {
+      await program.rpc.getRewardRunway({
+        accounts: {
+          state: legacyState,
+          ...defaultAccounts
+        }
+      })
+    } catch (error) {
+      loaded = false
+    }
+    assert.ok(!loaded)
+
+    const beginMigration = async (method, legacy) => {
+      const [migration, migrationBump] = await getMigrationAddress(legacy)
+      await program.rpc[method](migrationBump, {
+        accounts: {
+          legacy,
+          migration,
+          authority: creatorKey,
+          ...defaultAccounts
+        }
+      })
+      return migration
+    }
+    await assertError(beginMigration('beginMigrateState', stateSigner), 'Nothing to migrate')
+    let migration = await beginMigration('beginMigrateState', legacyState)
+    assert.ok(await connection.getAccountInfo(legacyState) === null)
+    // the freed address stays reserved for the migration
+    await assertError(program.rpc.createState(legacyStateBump, stateId, new BN(1), {
+      accounts: {
+        state: legacyState,
+        rewardMint: legacyMint.publicKey,
+        rewardVault: legacyRewardVault,
+        migration,
+        authority: user2.publicKey,
+        ...defaultAccounts
+      },
+      signers: [user2.user]
+    }), 'Migration pending')
+    await program.rpc.finishMigrateState(legacyStateBump, stateId, {
+      accounts: {
+        state: legacyState,
+        migration,
+        rewardVault: legacyRewardVault,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    assert.ok(await connection.getAccountInfo(migration) === null)
+    let stateInfo = await program.account.stateAccount.fetch(legacyState)
+    assert.ok(stateInfo.authority.equals(creatorKey))
+    assert.ok(stateInfo.rewardVault.equals(legacyRewardVault))
+    assert.ok(stateInfo.tokenPerSecond.eq(new BN(10)))
+    assert.ok(stateInfo.startTime.eq(new BN(now)))
+    assert.ok(stateInfo.stateId.eq(stateId))
+    assert.ok(stateInfo.totalFunded.eq(new BN(1000)))
+    assert.ok(stateInfo.totalPoint.eq(new BN(0)))
+    assert.ok(stateInfo.paused === 1 && stateInfo.unstakePaused === 1)
+
+    await program.rpc.createPoolRegistry(legacyRegistryBump, {
+      accounts: {
+        registry: legacyRegistry,
+        state: legacyState,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    migration = await beginMigration('beginMigratePool', legacyPool)
+    await program.rpc.finishMigratePool(legacyPoolBump, new BN(0), {
+      accounts: {
+        pool: legacyPool,
+        migration,
+        state: legacyState,
+        registry: legacyRegistry,
+        mint: legacyMint.publicKey,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    const poolInfo = await program.account.farmPoolAccount.fetch(legacyPool)
+    assert.ok(poolInfo.amount.eq(new BN(100)))
+    assert.ok(poolInfo.point.eq(new BN(1000)))
+    assert.ok(poolInfo.vault.equals(legacyPoolVault))
+    assert.ok(poolInfo.totalUser.eq(new BN(1)))
+    assert.ok(poolInfo.state.equals(legacyState))
+    assert.ok(poolInfo.stateId.eq(stateId))
+    assert.ok(poolInfo.unbondingPeriod.eq(new BN(0)))
+    stateInfo = await program.account.stateAccount.fetch(legacyState)
+    assert.ok(stateInfo.totalPoint.eq(new BN(1000)))
+    const registryInfo = await program.account.poolRegistryAccount.fetch(legacyRegistry)
+    assert.ok(registryInfo.pools.length === 1 && registryInfo.pools[0].equals(legacyPool))
+
+    migration = await beginMigration('beginMigrateExtraRewardConfigs', legacyExtra)
+    await program.rpc.finishMigrateExtraRewardConfigs(legacyExtraBump, {
+      accounts: {
+        extraRewardAccount: legacyExtra,
+        migration,
+        state: legacyState,
+        authority: creatorKey,
+        ...defaultAccounts
+      }
+    })
+    const extraInfo = await program.account.extraRewardsAccount.fetch(legacyExtra)
+    assert.ok(extraInfo.configs.length === 1)
+    assert.ok(extraInfo.version.eq(new BN(1)))
+    assert.ok(extraInfo.state.equals(legacyState))
+    assert.ok(!extraInfo.penalty.enabled)
+
+    await program.rpc.setPaused(false, false, {
+      accounts: {
+        state: legacyState,
+        authority: creatorKey,
+      }
+    })
+    stateInfo = await program.account.stateAccount.fetch(legacyState)
+    assert.ok(stateInfo.paused === 0 && stateInfo.unstakePaused === 0)
+
+    // a user of the legacy pool, staked before positions existed
+    const owner = Keypair.generate()
+    await connection.confirmTransaction(await connection.requestAirdrop(owner.publicKey, web3.LAMPORTS_PER_SOL))
+    const userSeeds = [legacyPool.toBuffer(), owner.publicKey.toBuffer()]
+    const [legacyUser, legacyUserBump] = await PublicKey.findProgramAddress(userSeeds, program.programId)
+    await writeLegacyFixture(userSeeds, Buffer.concat([
+      getDiscriminator('FarmPoolUserAccount'),
+      Buffer.from([legacyUserBump]),
+      legacyPool.toBuffer(),
+      owner.publicKey.toBuffer(),
+      new BN(100).toArrayLike(Buffer, 'le', 8), // amount
+      new BN(5).toArrayLike(Buffer, 'le', 16), // reward_amount
+      new BN(0).toArrayLike(Buffer, 'le', 16), // extra_reward
+      new BN(0).toArrayLike(Buffer, 'le', 16), // reward_debt
+      new BN(now).toArrayLike(Buffer, 'le', 8), // last_stake_time
+      new BN(0).toArrayLike(Buffer, 'le', 8), // lock_duration
+      Buffer.alloc(16 * 3), // reserved_1..3
+    ]))
+    const [legacyPosition, legacyPositionBump] = await getPositionAddress(legacyUser, 0)
+    await program.rpc.migrateUser(legacyPositionBump, {
+      accounts: {
+        position: legacyPosition,
+        user: legacyUser,
+        state: legacyState,
+        extraRewardAccount: legacyExtra,
+        pool: legacyPool,
+        authority: owner.publicKey,
+        ...defaultAccounts
+      },
+      signers: [owner]
+    })
+    const positionInfo = await program.account.farmPoolPositionAccount.fetch(legacyPosition)
+    assert.ok(positionInfo.amount.eq(new BN(100)))
+    assert.ok(positionInfo.rewardAmount.eq(new BN(5)))
+    assert.ok(positionInfo.lastStakeTime.eq(new BN(now)))
+    const legacyUserInfo = await program.account.farmPoolUserAccount.fetch(legacyUser)
+    assert.ok(legacyUserInfo.positionCount.eq(new BN(1)))
+    assert.ok(legacyUserInfo.legacyRewardAmount.eq(new BN(0)))
+    stateInfo = await program.account.stateAccount.fetch(legacyState)
+    assert.ok(stateInfo.rewardCommitted.eq(new BN(5)))
+
+    // the migrated stake unstakes from position 0
+    const ownerVault = await legacyMint.createAccount(owner.publicKey)
+    await program.rpc.unstake(new BN(100), {
+      accounts: {
+        mint: legacyMint.publicKey,
+        extraRewardAccount: legacyExtra,
+        poolVault: legacyPoolVault,
+        userVault: ownerVault,
+        user: legacyUser,
+        position: legacyPosition,
+        state: legacyState,
+        pool: legacyPool,
+        authority: owner.publicKey,
+        ...defaultAccounts
+      },
+      signers: [owner]
+    })
+    assert.ok((await getTokenAmount(ownerVault)).eq(new BN(100)))
+    assert.ok((await program.account.farmPoolAccount.fetch(legacyPool)).amount.eq(new BN(0)))
+  })
+})
+
+async function guardTime (time, fn) {
+  let completed = false
+  let tooShort = false
+  await Promise.all([fn().then(() => completed = true), sleep(time).then(() => tooShort = !completed)])
+  if (tooShort) console.error('SHORT')
+}
+
+async function assertUserReward (user, amount, showthrow = true) {
+  const realAmount = await getTokenAmount(user.rewardUserVault)
+  user.rewardAmount = realAmount
+  if (showthrow)
+    assert.ok(new BN(amount.toString()).eq(realAmount), `Expected ${amount.toString()} but got ${realAmount.toString()}`)
+}
+
+async function getPositionAddress (userAccount, index) {
+  return await PublicKey.findProgramAddress([
+    userAccount.toBuffer(), new BN(index).toArrayLike(Buffer, 'le', 8)
+  ], program.programId)
+}
+
+async function getMigrationAddress (account) {
+  return await PublicKey.findProgramAddress([
+    utf8.encode('migration'), account.toBuffer()
+  ], program.programId)
+}
+
+async function writeLegacyFixture (seeds, data) {
+  const [fixture, bump] = await PublicKey.findProgramAddress(seeds, program.programId)
+  await wrapError(program.rpc.writeLegacyFixture(seeds, bump, data, {
+    accounts: {
+      fixture,
+      payer: creatorKey,
+      systemProgram: SystemProgram.programId,
+      rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+    }
+  }))
+  return fixture
+}
+
+function getDiscriminator (name) {
+  return crypto.createHash('sha256').update(`account:${name}`).digest().slice(0, 8)
+}
+
+async function createPosition (u) {
+  const { positionCount } = await program.account.farmPoolUserAccount.fetch(u.userAccount1)
+  const [position, bump] = await getPositionAddress(u.userAccount1, positionCount)
+  const tx = program.transaction.createPosition(bump, {
+    accounts: {
+      position,
+      user: u.userAccount1,
+      pool: poolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  })
+  await u.provider.send(tx, [], { commitment: 'confirmed' })
+  u.positionAccounts.push(position)
+  return position
+}
+
+async function createPositionLP (u) {
+  const { positionCount } = await program.account.farmPoolUserAccount.fetch(u.lpUserAccount)
+  const [position, bump] = await getPositionAddress(u.lpUserAccount, positionCount)
+  const tx = program.transaction.createPosition(bump, {
+    accounts: {
+      position,
+      user: u.lpUserAccount,
+      pool: lpPoolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  })
+  await u.provider.send(tx, [], { commitment: 'confirmed' })
+  u.lpPositionAccounts.push(position)
+  return position
+}
+
+async function harvestAll (u) {
+  const tx = program.transaction.harvestAll({
+    accounts: {
+      extraRewardAccount: extraRewardSigner,
+      rewardVault: stateRewardVault,
+      userVault: u.rewardUserVault,
+      user: u.userAccount1,
+      state: stateSigner,
+      pool: poolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    },
+    remainingAccounts: u.positionAccounts.map(p => ({
+      pubkey: p,
+      isWritable: true,
+      isSigner: false
+    }))
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function unstake (u, amount, index = 0) {
+  const tx = program.transaction.unstake(amount, {
+    accounts: {
+      mint: rewardMint.publicKey,
+      extraRewardAccount: extraRewardSigner,
+      poolVault: poolVault,
+      userVault: u.rewardUserVault,
+      user: u.userAccount1,
+      position: u.positionAccounts[index],
+      state: stateSigner,
+      pool: poolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function emergencyWithdraw (u, index) {
+  const tx = program.transaction.emergencyWithdraw({
+    accounts: {
+      mint: rewardMint.publicKey,
+      extraRewardAccount: extraRewardSigner,
+      poolVault: poolVault,
+      userVault: u.rewardUserVault,
+      user: u.userAccount1,
+      position: u.positionAccounts[index],
+      state: stateSigner,
+      pool: poolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function earlyUnstake (u, amount, index, treasuryVault) {
+  const tx = program.transaction.earlyUnstake(amount, {
+    accounts: {
+      mint: rewardMint.publicKey,
+      extraRewardAccount: extraRewardSigner,
+      poolVault: poolVault,
+      userVault: u.rewardUserVault,
+      treasuryVault,
+      user: u.userAccount1,
+      position: u.positionAccounts[index],
+      state: stateSigner,
+      pool: poolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function harvest (u, index = 0) {
+  const tx = program.transaction.harvest({
+    accounts: {
+      mint: rewardMint.publicKey,
+      extraRewardAccount: extraRewardSigner,
+      rewardVault: stateRewardVault,
+      userVault: u.rewardUserVault,
+      user: u.userAccount1,
+      position: u.positionAccounts[index],
+      state: stateSigner,
+      pool: poolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function stake (user, amount, lock = 0, index = 0) {
+  const tx = program.transaction.stake(amount, new BN(lock), {
+    accounts: {
+      mint: rewardMint.publicKey,
+      extraRewardAccount: extraRewardSigner,
+      poolVault: poolVault,
+      userVault: user.rewardUserVault,
+      user: user.userAccount1,
+      position: user.positionAccounts[index],
+      state: stateSigner,
+      pool: poolSigner,
+      authority: user.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await user.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function unstakeLP (u, amount) {
+  const tx = program.transaction.unstake(amount, {
+    accounts: {
+      mint: lpMint.publicKey,
+      extraRewardAccount: lpExtraRewardSigner,
+      poolVault: lpPoolVault,
+      userVault: u.lpUserVault,
+      user: u.lpUserAccount,
+      position: u.lpPositionAccounts[0],
+      state: stateSigner,
+      pool: lpPoolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function requestUnstakeLP (u, amount) {
+  const tx = program.transaction.requestUnstake(amount, {
+    accounts: {
+      mint: lpMint.publicKey,
+      extraRewardAccount: lpExtraRewardSigner,
+      poolVault: lpPoolVault,
+      userVault: u.lpUserVault,
+      user: u.lpUserAccount,
+      position: u.lpPositionAccounts[0],
+      state: stateSigner,
+      pool: lpPoolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function withdrawUnbondedLP (u) {
+  const tx = program.transaction.withdrawUnbonded({
+    accounts: {
+      mint: lpMint.publicKey,
+      extraRewardAccount: lpExtraRewardSigner,
+      poolVault: lpPoolVault,
+      userVault: u.lpUserVault,
+      user: u.lpUserAccount,
+      position: u.lpPositionAccounts[0],
+      state: stateSigner,
+      pool: lpPoolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    }
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function emergencyWithdrawLP (u, remainingAccounts = []) {
+  const tx = program.transaction.emergencyWithdraw({
+    accounts: {
+      mint: lpMint.publicKey,
+      extraRewardAccount: lpExtraRewardSigner,
+      poolVault: lpPoolVault,
+      userVault: u.lpUserVault,
+      user: u.lpUserAccount,
+      position: u.lpPositionAccounts[0],
+      state: stateSigner,
+      pool: lpPoolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    },
+    remainingAccounts: remainingAccounts.map(pubkey => ({ pubkey, isWritable: true, isSigner: false }))
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function harvestLP (u, remainingAccounts = []) {
+  const tx = program.transaction.harvest({
+    accounts: {
+      mint: lpMint.publicKey,
+      rewardVault: stateRewardVault,
+      extraRewardAccount: lpExtraRewardSigner,
+      userVault: u.rewardUserVault,
+      user: u.lpUserAccount,
+      position: u.lpPositionAccounts[0],
+      state: stateSigner,
+      pool: lpPoolSigner,
+      authority: u.publicKey,
+      ...defaultAccounts
+    },
+    remainingAccounts: remainingAccounts.map(pubkey => ({ pubkey, isWritable: true, isSigner: false }))
+  });
+  const hash = await u.provider.send(tx, [], { commitment: 'confirmed' });
+  return await cccc.getTransaction(hash)
+}
+
+async function stakeLP (user, amount, lock = 0, extraRewardAccount = lpExtraRewardSigner, remainingAccounts = []) {
+  const tx = program.transaction.stake(amount, new BN(lock), {
+    accounts: {
+      mint: lpMint.publicKey,
+      poolVault: lpPoolVault,
+      extraRewardAccount,
+      userVault: user.lpUserVault,
+      user: user.lpUserAccount,
+      position: user.lpPositionAccounts[0],
+      state: stateSigner,
+      pool: lpPoolSigner,
+      authority: user.publicKey,
+      ...defaultAccounts
+    },
+    remainingAccounts: remainingAccounts.map(pubkey => ({ pubkey, isWritable: true, isSigner: false }))
   });
   const hash = await user.provider.send(tx, [], { commitment: 'confirmed' });
   return await cccc.getTransaction(hash)